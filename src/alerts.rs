@@ -0,0 +1,292 @@
+use std::f32::consts::PI;
+use std::time::Instant;
+
+use serde::{Serialize, Deserialize};
+
+use crate::colors::ColorHsv;
+use crate::font;
+use crate::params::{ParamValue, Params};
+
+// upper bound on queued alerts per device, so a misbehaving client can't grow it forever
+pub const MAX_QUEUED: usize = 32;
+
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertMode {
+    // play on top of the current state
+    #[default]
+    Overlay,
+    // blank the current state while the alert plays
+    Replace,
+}
+
+/// A temporary effect that pulses `repeat` times, `duration` seconds each, then gives the
+/// device back to its state.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Alert {
+    pub color: ColorHsv,
+    #[serde(default)]
+    pub color2: Option<ColorHsv>,
+    #[serde(default)]
+    pub color3: Option<ColorHsv>,
+    // pattern to pulse, a solid fill of `color` if not given
+    #[serde(default)]
+    pub pattern: Option<u8>,
+    #[serde(default)]
+    pub params: Params,
+    #[serde(default = "default_duration")]
+    pub duration: f32,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    // higher priorities interrupt lower ones and jump ahead of them in the queue
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default)]
+    pub mode: AlertMode,
+    // fade in and out on every repeat, or hold at full strength throughout
+    #[serde(default = "default_fade")]
+    pub fade: bool,
+}
+
+fn default_duration() -> f32 {
+    1.0
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+fn default_fade() -> bool {
+    true
+}
+
+impl Alert {
+    pub fn colors(&self) -> [ColorHsv; 3] {
+        [self.color, self.color2.unwrap_or(self.color), self.color3.unwrap_or(self.color)]
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !(self.duration > 0.0 && self.duration <= 60.0) {
+            return Err("duration must be between 0 and 60 seconds");
+        }
+        if self.repeat == 0 || self.repeat > 100 {
+            return Err("repeat must be between 1 and 100");
+        }
+        Ok(())
+    }
+}
+
+// longest message and range of scroll rates in pixels per second for `/text`
+pub const MAX_TEXT: usize = 200;
+const TEXT_RATES: (f32, f32) = (1.0, 100.0);
+
+/// A message to scroll across a matrix with the text pattern, queued as an alert so the
+/// device goes back to its state afterwards.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct TextMessage {
+    pub text: String,
+    // text color, white if not given
+    #[serde(default)]
+    pub color1: Option<ColorHsv>,
+    // background color, black if not given
+    #[serde(default)]
+    pub color2: Option<ColorHsv>,
+    #[serde(default = "default_rate")]
+    pub rate: f32,
+    // times the message scrolls all the way across
+    #[serde(default = "default_repeat")]
+    pub loops: u32,
+    #[serde(default)]
+    pub priority: u8,
+}
+
+fn default_rate() -> f32 {
+    20.0
+}
+
+impl TextMessage {
+    /// The alert that scrolls the message `loops` times across a display `width` pixels wide,
+    /// each pass lasting until the text has gone off the other side.
+    pub fn to_alert(&self, width: usize) -> Result<Alert, &'static str> {
+        let chars = self.text.chars().count();
+        if chars == 0 || chars > MAX_TEXT {
+            return Err("text must be between 1 and 200 characters");
+        }
+        if !(self.rate >= TEXT_RATES.0 && self.rate <= TEXT_RATES.1) {
+            return Err("rate must be between 1 and 100 pixels per second");
+        }
+        if self.loops == 0 || self.loops > 100 {
+            return Err("loops must be between 1 and 100");
+        }
+        let pass = (font::columns(&self.text).len() + width) as f32 / self.rate;
+        Ok(Alert {
+            color: self.color1.unwrap_or(ColorHsv::new(0.0, 0.0, 1.0)),
+            color2: Some(self.color2.unwrap_or(ColorHsv::new(0.0, 0.0, 0.0))),
+            color3: None,
+            pattern: Some(26),
+            params: Params::new()
+                .with("text", ParamValue::Text(self.text.clone()))
+                .with("rate", ParamValue::Number(self.rate)),
+            duration: pass,
+            repeat: self.loops,
+            priority: self.priority,
+            mode: AlertMode::Replace,
+            fade: false,
+        })
+    }
+}
+
+/// Pending alerts for one device, highest priority first and in arrival order within a
+/// priority. The front alert is the one playing.
+#[derive(Debug, Default)]
+pub struct AlertQueue {
+    queue: Vec<Alert>,
+    started: Option<Instant>,
+}
+
+impl AlertQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, alert: Alert) -> Result<(), &'static str> {
+        if self.queue.len() >= MAX_QUEUED {
+            return Err("alert queue full");
+        }
+        let index = self.queue.iter().position(|a| a.priority < alert.priority).unwrap_or(self.queue.len());
+        if index == 0 {
+            // the interrupted alert starts over once this one is done
+            self.started = None;
+        }
+        self.queue.insert(index, alert);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.started = None;
+    }
+
+    /// The alert playing at `now`, its level from 0.0 to 1.0 and whether it started or
+    /// restarted with this call, dropping finished alerts. An alert that starts again after
+    /// being interrupted needs its animation reset, the same as a new one.
+    pub fn current(&mut self, now: Instant) -> Option<(&Alert, f32, bool)> {
+        loop {
+            let alert = self.queue.first()?;
+            let restarted = self.started.is_none();
+            let started = *self.started.get_or_insert(now);
+            let elapsed = now.saturating_duration_since(started).as_secs_f32() / alert.duration;
+            if elapsed < alert.repeat as f32 {
+                // one half sine pulse per repeat
+                let level = if alert.fade { (elapsed.fract() * PI).sin() } else { 1.0 };
+                return Some((&self.queue[0], level, restarted));
+            }
+            self.queue.remove(0);
+            self.started = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::animation::{Animation, Frame};
+    use crate::maps::PixelMap;
+    use crate::patterns;
+
+    fn alert(priority: u8, repeat: u32) -> Alert {
+        Alert {
+            color: ColorHsv::new(0.0, 1.0, 1.0),
+            color2: None,
+            color3: None,
+            pattern: None,
+            params: Params::new(),
+            duration: 1.0,
+            repeat,
+            priority,
+            mode: AlertMode::Overlay,
+            fade: true,
+        }
+    }
+
+    #[test]
+    fn test_alert_queue() {
+        let start = Instant::now();
+        let mut queue = AlertQueue::new();
+        assert!(queue.current(start).is_none());
+
+        queue.push(alert(0, 3)).unwrap();
+        queue.push(alert(0, 1)).unwrap();
+
+        // -test- pulses peak halfway through each repeat
+        let (a, level, restarted) = queue.current(start).unwrap();
+        assert_eq!(a.repeat, 3);
+        assert_eq!(level, 0.0);
+        assert!(restarted);
+        let (_, level, restarted) = queue.current(start + Duration::from_millis(2500)).unwrap();
+        assert!(!restarted);
+        assert!((level - 1.0).abs() < 0.001);
+
+        // -test- a higher priority alert interrupts, the interrupted one restarts afterwards
+        queue.push(alert(5, 1)).unwrap();
+        let (a, _, restarted) = queue.current(start + Duration::from_millis(2600)).unwrap();
+        assert_eq!(a.priority, 5);
+        assert!(restarted);
+        let (a, _, restarted) = queue.current(start + Duration::from_millis(3700)).unwrap();
+        assert_eq!(a.repeat, 3);
+        assert!(restarted);
+        assert_eq!(queue.queue.len(), 2);
+
+        // -test- finished alerts are dropped and the queue plays out in order
+        let (a, _, _) = queue.current(start + Duration::from_millis(6800)).unwrap();
+        assert_eq!(a.repeat, 1);
+        assert!(queue.current(start + Duration::from_millis(8000)).is_none());
+        assert!(queue.queue.is_empty());
+    }
+
+    #[test]
+    fn test_text_message() {
+        let message: TextMessage = serde_json::from_str(r#"{"text": "Hi", "rate": 10, "loops": 2}"#).unwrap();
+
+        // -test- each pass lasts until the text has crossed the whole display
+        let alert = message.to_alert(8).unwrap();
+        assert_eq!(alert.pattern, Some(26));
+        assert_eq!(alert.duration, 2.0);
+        assert_eq!(alert.repeat, 2);
+        assert_eq!(alert.mode, AlertMode::Replace);
+
+        // -test- the message is shown at full strength, then the queue empties
+        let start = Instant::now();
+        let mut queue = AlertQueue::new();
+        queue.push(alert).unwrap();
+        assert_eq!(queue.current(start).map(|(_, level, _)| level), Some(1.0));
+        assert!(queue.current(start + Duration::from_millis(4100)).is_none());
+
+        // -test- an interrupted message scrolls in from the start again once it resumes
+        let map = PixelMap::linear(8);
+        let mut anim = Animation::new();
+        let mut play = |queue: &mut AlertQueue, at: u64, dt: f32| {
+            let (alert, _, restarted) = queue.current(start + Duration::from_millis(at)).unwrap();
+            if restarted {
+                anim = Animation::new();
+            }
+            if let Some(pattern) = alert.pattern {
+                patterns::step(pattern, &alert.params, &mut anim, &map, &Frame { dt, ..Default::default() });
+            }
+            anim.get_scroll()
+        };
+        let text = message.to_alert(8).unwrap();
+        queue.push(text.clone()).unwrap();
+        play(&mut queue, 0, 0.0);
+        assert_eq!(play(&mut queue, 1000, 1.0), 10.0);
+        queue.push(Alert { pattern: None, params: Params::new(), duration: 1.0, repeat: 1, priority: 5, ..text }).unwrap();
+        play(&mut queue, 1100, 0.1);
+        assert_eq!(play(&mut queue, 2200, 0.1), 0.0);
+        assert_eq!(play(&mut queue, 3200, 1.0), 10.0);
+
+        let empty: TextMessage = serde_json::from_str(r#"{"text": ""}"#).unwrap();
+        assert!(empty.to_alert(8).is_err());
+    }
+}
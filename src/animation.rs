@@ -0,0 +1,396 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::NaiveDateTime;
+
+use crate::audio::AudioLevels;
+use crate::automata::Automaton;
+use crate::colors::ColorRgb16;
+use crate::fire::Fire;
+use crate::maps::PixelMap;
+use crate::media::{MediaFrame, MediaLibrary};
+use crate::meter::Meter;
+use crate::params::Params;
+use crate::plugins::{PluginInstance, Plugins};
+use crate::scripts::Scripts;
+use crate::sprites::{Ball, Comet, Sprite};
+use crate::twinkle::Twinkles;
+
+/// What the render loop knows about the frame being drawn, shared by every animation.
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    // seconds since the last frame
+    pub dt: f32,
+    // local wall clock time
+    pub now: NaiveDateTime,
+    pub audio: AudioLevels,
+    // brightness from the config, for patterns such as scripts that make their own colors
+    pub brightness: f32,
+    pub scripts: Option<Arc<Mutex<Scripts>>>,
+    pub plugins: Option<Arc<Plugins>>,
+    pub media: Option<Arc<Mutex<MediaLibrary>>>,
+}
+
+/// Per-device animation state, advanced once per frame using the device's own params.
+#[derive(Debug)]
+pub struct Animation {
+    // phase of scrolling patterns, wraps from 1.0 back to 0.0
+    offset: f32,
+    // same as `offset`, but without wrapping
+    travel: f32,
+    // seconds since the animation started
+    time: f32,
+    // local wall clock time of the current frame
+    local_time: NaiveDateTime,
+    // brightness from the config, for patterns that pick their own colors
+    brightness: f32,
+    audio: AudioLevels,
+    // brightness of the beat flash, and the beat count it last flashed for
+    flash: f32,
+    beats: u64,
+    sprites: Vec<Sprite>,
+    // count, falloff and max speed the sprites were created with
+    sprite_params: (usize, f32, f32),
+    comets: Vec<Comet>,
+    // count, direction and wrapping the comets were created with
+    comet_params: (usize, f32, bool),
+    balls: Vec<Ball>,
+    // count and restitution the balls were created with
+    ball_params: (usize, f32),
+    automaton: Automaton,
+    // frames since the last generation
+    generation_frame: usize,
+    // how far the display has faded from the last generation to the current one
+    generation_progress: f32,
+    meter: Meter,
+    fire: Fire,
+    twinkles: Twinkles,
+    // colors made by a script or plugin in the last frame
+    pixels: Vec<ColorRgb16>,
+    // media being played and seconds into it, sped up or slowed down by `speed`
+    media: String,
+    media_time: f32,
+    // the media's current step as last sampled onto the device
+    media_frame: MediaFrame,
+    // text being scrolled and how many pixels it has moved
+    text: String,
+    scroll: f32,
+    // plugin pattern the animation last ran and its running copy, if it started
+    plugin: Option<(u8, Option<PluginInstance>)>,
+    // source of every random choice, seeded from the `seed` param so runs can be replayed
+    rng: fastrand::Rng,
+    seed: usize,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        Self {
+            offset: 0.0,
+            travel: 0.0,
+            time: 0.0,
+            local_time: NaiveDateTime::default(),
+            brightness: 0.0,
+            audio: AudioLevels::default(),
+            flash: 0.0,
+            beats: 0,
+            sprites: Vec::new(),
+            sprite_params: (0, 0.0, 0.0),
+            comets: Vec::new(),
+            comet_params: (0, 0.0, false),
+            balls: Vec::new(),
+            ball_params: (0, 0.0),
+            automaton: Automaton::new(),
+            generation_frame: 0,
+            generation_progress: 1.0,
+            meter: Meter::new(),
+            fire: Fire::new(),
+            twinkles: Twinkles::new(),
+            pixels: Vec::new(),
+            media: String::new(),
+            media_time: 0.0,
+            media_frame: MediaFrame::new(),
+            text: String::new(),
+            scroll: 0.0,
+            plugin: None,
+            rng: fastrand::Rng::new(),
+            seed: 0,
+        }
+    }
+
+    pub fn get_offset(&self) -> f32 {
+        self.offset
+    }
+
+    pub fn get_travel(&self) -> f32 {
+        self.travel
+    }
+
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn get_local_time(&self) -> NaiveDateTime {
+        self.local_time
+    }
+
+    pub fn get_brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    pub fn get_audio(&self) -> &AudioLevels {
+        &self.audio
+    }
+
+    pub fn get_flash(&self) -> f32 {
+        self.flash
+    }
+
+    pub fn get_sprites(&self) -> &[Sprite] {
+        &self.sprites
+    }
+
+    pub fn get_comets(&self) -> &[Comet] {
+        &self.comets
+    }
+
+    pub fn get_balls(&self) -> &[Ball] {
+        &self.balls
+    }
+
+    pub fn get_automaton(&self) -> (&Automaton, f32) {
+        (&self.automaton, self.generation_progress)
+    }
+
+    pub fn get_meter(&self) -> &Meter {
+        &self.meter
+    }
+
+    pub fn get_fire(&self) -> &Fire {
+        &self.fire
+    }
+
+    pub fn get_twinkles(&self) -> &Twinkles {
+        &self.twinkles
+    }
+
+    pub fn get_scroll(&self) -> f32 {
+        self.scroll
+    }
+
+    pub fn get_pixels(&self) -> &[ColorRgb16] {
+        &self.pixels
+    }
+
+    /// Advances the clock to `frame` and the scroll position by `speed` in `direction`, and
+    /// restarts the random sequence when `seed` changes. A seed of 0 is unseeded.
+    pub fn update(&mut self, params: &Params, frame: &Frame) {
+        self.time += frame.dt;
+        self.local_time = frame.now;
+        self.brightness = frame.brightness;
+        self.audio.clone_from(&frame.audio);
+        let seed = params.get_usize("seed", 0);
+        if seed != self.seed {
+            self.rng = match seed {
+                0 => fastrand::Rng::new(),
+                _ => fastrand::Rng::with_seed(seed as u64),
+            };
+            self.seed = seed;
+        }
+
+        let step = params.get_f32("speed", 0.0) * params.get_direction();
+        self.offset = (self.offset + step).rem_euclid(1.0);
+        self.travel += step;
+    }
+
+    /// Moves the sprites, recreating them when their params have changed.
+    pub fn update_sprites(&mut self, params: &Params) {
+        let sprite_params = (
+            params.get_usize("count", 0).min(256),
+            params.get_f32("falloff", 4.0),
+            params.get_f32("max_speed", 0.01),
+        );
+        if sprite_params != self.sprite_params {
+            let (count, falloff, max_speed) = sprite_params;
+            self.sprites = (0..count)
+                .map(|_| {
+                    let pos = self.rng.f32();
+                    let lane = self.rng.f32() * 1000.0;
+                    Sprite::new(pos, falloff, lane, max_speed)
+                })
+                .collect();
+            self.sprite_params = sprite_params;
+        }
+        for sprite in &mut self.sprites {
+            sprite.run();
+        }
+    }
+
+    /// Moves the comets, spacing them out evenly again when their params have changed.
+    pub fn update_comets(&mut self, params: &Params) {
+        let wrap = params.get_str("ends", "wrap") == "wrap";
+        let comet_params = (params.get_usize("count", 1).min(64), params.get_direction(), wrap);
+        if comet_params != self.comet_params {
+            let (count, heading, _) = comet_params;
+            self.comets = (0..count)
+                .map(|i| Comet::new(i as f32 / count as f32, heading))
+                .collect();
+            self.comet_params = comet_params;
+        }
+        let speed = params.get_f32("speed", 0.005);
+        let length = params.get_f32("length", 0.2);
+        for comet in &mut self.comets {
+            comet.run(speed, length, wrap);
+        }
+    }
+
+    /// Moves the balls `dt` seconds, dropping them all from the top again when their params
+    /// have changed. Each ball bounces a little less than the one before so they drift apart.
+    pub fn update_balls(&mut self, params: &Params, dt: f32) {
+        let ball_params = (params.get_usize("count", 3).min(16), params.get_f32("restitution", 0.85));
+        if ball_params != self.ball_params {
+            let (count, restitution) = ball_params;
+            self.balls = (0..count).map(|i| Ball::new(restitution - i as f32 * 0.03)).collect();
+            self.ball_params = ball_params;
+        }
+        let gravity = params.get_f32("gravity", 9.8);
+        for ball in &mut self.balls {
+            ball.run(dt, gravity);
+        }
+    }
+
+    /// Runs a cellular automaton over the pixel map: Life if it is a matrix, the elementary
+    /// automaton numbered `rule` if not. A new generation comes every `frames` frames.
+    pub fn update_automaton(&mut self, params: &Params, map: &PixelMap) {
+        let size = match map.get_matrix() {
+            Some(matrix) => (matrix.width, matrix.height),
+            None => (map.len(), 1),
+        };
+        if self.automaton.size() != size {
+            self.automaton.reseed(size.0, size.1, &mut self.rng);
+            self.generation_frame = 0;
+        }
+        let frames = params.get_usize("frames", 5).max(1);
+        self.generation_frame += 1;
+        if self.generation_frame >= frames {
+            self.automaton.step(params.get_usize("rule", 30).min(255) as u8, &mut self.rng);
+            self.generation_frame = 0;
+        }
+        self.generation_progress = (self.generation_frame + 1) as f32 / frames as f32;
+    }
+
+    pub fn update_meter(&mut self, params: &Params, dt: f32) {
+        let target = params.get_f32("value", 0.0);
+        self.meter.step(target, params.get_f32("smoothing", 0.3), params.get_f32("peak_hold", 1.5), dt);
+    }
+
+    /// Lights the flash on each new beat and fades it out over `decay` seconds.
+    pub fn update_flash(&mut self, params: &Params, dt: f32) {
+        if self.audio.beats != self.beats {
+            self.beats = self.audio.beats;
+            self.flash = 1.0;
+        } else {
+            let decay = params.get_f32("decay", 0.3).max(0.01);
+            self.flash = (self.flash - dt / decay).max(0.0);
+        }
+    }
+
+    pub fn update_fire(&mut self, params: &Params, len: usize) {
+        let cooling = params.get_usize("cooling", 55).min(255) as u8;
+        let sparking = params.get_usize("sparking", 120).min(255) as u8;
+        self.fire.step(len, cooling, sparking, &mut self.rng);
+    }
+
+    pub fn update_twinkles(&mut self, params: &Params, len: usize) {
+        let density = params.get_f32("density", 0.01);
+        let fade = params.get_f32("fade", 0.02);
+        self.twinkles.step(len, density, fade, &mut self.rng);
+    }
+
+    /// Runs the script named by the `script` param for `len` pixels.
+    pub fn update_script(&mut self, params: &Params, len: usize, frame: &Frame) {
+        let pixels = match &frame.scripts {
+            Some(scripts) => {
+                let mut scripts = match scripts.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                scripts.run(params.get_str("script", ""), self.time, len, params)
+            }
+            None => Vec::new(),
+        };
+        self.set_pixels(pixels, len, frame.brightness);
+    }
+
+    /// Scrolls the `text` param `rate` pixels per second, from the start whenever it changes.
+    pub fn update_text(&mut self, params: &Params, dt: f32) {
+        let text = params.get_str("text", "");
+        if text != self.text {
+            self.text = String::from(text);
+            self.scroll = 0.0;
+        } else {
+            self.scroll += dt * params.get_f32("rate", 20.0);
+        }
+    }
+
+    /// Plays the media named by the `media` param on the LEDs laid out by `map`, from the start
+    /// whenever it changes. It goes dark once it has played `loops` times, unless that is 0.
+    pub fn update_media(&mut self, params: &Params, map: &PixelMap, frame: &Frame) {
+        let name = params.get_str("media", "");
+        if name != self.media {
+            self.media = String::from(name);
+            self.media_time = 0.0;
+        } else {
+            self.media_time += frame.dt * params.get_f32("speed", 1.0);
+        }
+        let pixels = match &frame.media {
+            Some(media) => {
+                let media = match media.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                media.get(name)
+                    .and_then(|m| m.step_at(self.media_time, params.get_usize("loops", 0)).map(|step| self.media_frame.render(m, step, map).to_vec()))
+                    .unwrap_or_default()
+            }
+            None => Vec::new(),
+        };
+        self.set_pixels(pixels.into_iter().map(ColorRgb16::from).collect(), map.len(), frame.brightness);
+    }
+
+    /// Runs the plugin for `pattern` for `len` pixels, starting a copy of it the first time.
+    /// A plugin that fails is stopped and leaves the pixels dark until the pattern changes.
+    pub fn update_plugin(&mut self, pattern: u8, params: &Params, len: usize, frame: &Frame) {
+        let plugins = match &frame.plugins {
+            Some(plugins) => plugins,
+            None => return,
+        };
+        if self.plugin.as_ref().map(|(p, _)| *p) != Some(pattern) {
+            let instance = plugins.instantiate(pattern).map_err(|err| println!("error starting plugin {}: {}", pattern, err)).ok();
+            self.plugin = Some((pattern, instance));
+        }
+        let pixels = match &mut self.plugin {
+            Some((_, Some(instance))) => match instance.render(len, self.time, params) {
+                Ok(pixels) => pixels,
+                Err(err) => {
+                    println!("error in plugin {}: {}", pattern, err);
+                    self.plugin = Some((pattern, None));
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        };
+        self.set_pixels(pixels.into_iter().map(ColorRgb16::from).collect(), len, frame.brightness);
+    }
+
+    // keeps `pixels` for rendering, dimmed to `brightness` at 16 bits and padded with black to `len`
+    fn set_pixels(&mut self, pixels: Vec<ColorRgb16>, len: usize, brightness: f32) {
+        let dim = |c: u16| (c as f32 * brightness.clamp(0.0, 1.0)).round() as u16;
+        self.pixels = pixels.into_iter().map(|p| ColorRgb16::new(dim(p.get_r()), dim(p.get_g()), dim(p.get_b()))).collect();
+        self.pixels.resize(len, ColorRgb16::default());
+    }
+}
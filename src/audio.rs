@@ -0,0 +1,305 @@
+// audio analysis for the sound-reactive patterns: raw PCM is read from a file, stdin or a
+// named pipe, cut into overlapping windows and run through an FFT to get the overall level,
+// the level of each frequency band and a count of the beats heard so far
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Deserialize;
+
+// samples per FFT window, and how far the window moves between analyses
+const WINDOW: usize = 1024;
+const HOP: usize = 512;
+// levels are shown on a decibel scale from this much below full scale up to full scale
+const RANGE_DB: f32 = 60.0;
+// share of a band's level kept per analysis when the sound gets quieter, so bars fall smoothly
+const RELEASE: f32 = 0.85;
+// bass energy over its average that counts as a beat, the shortest gap between beats in
+// seconds, and the seconds of history the average is taken over
+const BEAT_THRESHOLD: f32 = 1.4;
+const BEAT_GAP: f32 = 0.25;
+const BEAT_HISTORY: f32 = 1.0;
+// frequencies up to this count as bass for beat detection
+const BASS_HZ: f32 = 150.0;
+// sample rates the input can be given at, from telephone quality to high resolution audio
+pub const MIN_SAMPLE_RATE: u32 = 8000;
+pub const MAX_SAMPLE_RATE: u32 = 192000;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AudioConfig {
+    // path of a file or named pipe with signed 16-bit little-endian mono samples, or "-" for
+    // stdin
+    pub source: String,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: u32,
+    #[serde(default = "default_bands")]
+    pub bands: usize,
+}
+
+fn default_sample_rate() -> u32 {
+    44100
+}
+
+fn default_bands() -> usize {
+    16
+}
+
+/// The latest analysis, shared with the render loop.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioLevels {
+    // overall loudness, 0.0 to 1.0
+    pub level: f32,
+    // loudness of each band from low to high frequencies, 0.0 to 1.0
+    pub bands: Vec<f32>,
+    // beats heard since the input started, so each one is seen once however often it is read
+    pub beats: u64,
+}
+
+pub struct Analyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    samples: Vec<f32>,
+    // FFT bins at the edges of each band
+    edges: Vec<usize>,
+    bass_bins: usize,
+    sample_rate: u32,
+    energy_history: Vec<f32>,
+    since_beat: usize,
+    levels: AudioLevels,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32, bands: usize) -> Self {
+        let bands = bands.clamp(1, 64);
+        let bin_hz = sample_rate as f32 / WINDOW as f32;
+        // bands are spaced evenly on a log scale from 40 Hz up to 16 kHz or the Nyquist limit
+        let (low, high) = (40.0f32, (sample_rate as f32 / 2.0).min(16000.0));
+        let mut edges: Vec<usize> = (0..=bands)
+            .map(|i| {
+                let hz = low * (high / low).powf(i as f32 / bands as f32);
+                ((hz / bin_hz) as usize).clamp(1, WINDOW / 2)
+            })
+            .collect();
+        // every band gets at least one bin, as far as there are bins to go round
+        for i in 1..edges.len() {
+            edges[i] = edges[i].max(edges[i - 1] + 1).min(WINDOW / 2);
+        }
+        let window = (0..WINDOW)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / WINDOW as f32).cos())
+            .collect();
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(WINDOW),
+            window,
+            samples: Vec::with_capacity(WINDOW * 2),
+            edges,
+            bass_bins: ((BASS_HZ / bin_hz) as usize).clamp(2, WINDOW / 2),
+            sample_rate,
+            energy_history: Vec::new(),
+            since_beat: usize::MAX / 2,
+            levels: AudioLevels { bands: vec![0.0; bands], ..Default::default() },
+        }
+    }
+
+    pub fn get_levels(&self) -> &AudioLevels {
+        &self.levels
+    }
+
+    /// Adds samples from -1.0 to 1.0, analysing each full window. Returns whether the levels
+    /// changed.
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        self.samples.extend_from_slice(samples);
+        let mut changed = false;
+        while self.samples.len() >= WINDOW {
+            self.analyse();
+            self.samples.drain(..HOP);
+            changed = true;
+        }
+        changed
+    }
+
+    fn analyse(&mut self) {
+        let frame = &self.samples[..WINDOW];
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / WINDOW as f32).sqrt();
+        self.levels.level = to_level(rms * std::f32::consts::SQRT_2);
+
+        let mut buffer: Vec<Complex<f32>> = frame.iter().zip(&self.window).map(|(s, w)| Complex::new(s * w, 0.0)).collect();
+        self.fft.process(&mut buffer);
+        // a full scale sine comes out at 1.0
+        let norm = 2.0 / self.window.iter().sum::<f32>();
+        let amplitudes: Vec<f32> = buffer[..WINDOW / 2].iter().map(|c| c.norm() * norm).collect();
+
+        for (band, edge) in self.levels.bands.iter_mut().zip(self.edges.windows(2)) {
+            let peak = amplitudes[edge[0]..edge[1]].iter().fold(0.0f32, |a, b| a.max(*b));
+            *band = to_level(peak).max(*band * RELEASE);
+        }
+
+        let energy: f32 = amplitudes[1..self.bass_bins].iter().map(|a| a * a).sum();
+        let history_len = (BEAT_HISTORY * self.sample_rate as f32 / HOP as f32) as usize;
+        let average = self.energy_history.iter().sum::<f32>() / self.energy_history.len().max(1) as f32;
+        self.since_beat = self.since_beat.saturating_add(HOP);
+        let gap = (BEAT_GAP * self.sample_rate as f32) as usize;
+        if self.energy_history.len() >= history_len / 2 && energy > average * BEAT_THRESHOLD && energy > 1e-4 && self.since_beat >= gap {
+            self.levels.beats += 1;
+            self.since_beat = 0;
+        }
+        self.energy_history.push(energy);
+        if self.energy_history.len() > history_len {
+            self.energy_history.remove(0);
+        }
+    }
+}
+
+// amplitude relative to full scale onto 0.0 to 1.0, by decibels
+fn to_level(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return 0.0;
+    }
+    ((20.0 * amplitude.log10() + RANGE_DB) / RANGE_DB).clamp(0.0, 1.0)
+}
+
+/// Reads the configured source on its own thread and keeps `levels` up to date. A regular file
+/// is played at its sample rate and looped, a pipe is reopened when its writer goes away.
+pub fn spawn(config: AudioConfig, levels: Arc<Mutex<AudioLevels>>) {
+    thread::spawn(move || {
+        let mut analyzer = Analyzer::new(config.sample_rate, config.bands);
+        let hop_time = Duration::from_secs_f32(HOP as f32 / config.sample_rate as f32);
+        let mut bytes = vec![0u8; HOP * 2];
+        loop {
+            let (mut input, is_file): (Box<dyn Read>, bool) = if config.source == "-" {
+                (Box::new(io::stdin()), false)
+            } else {
+                match File::open(&config.source) {
+                    Ok(f) => {
+                        let is_file = f.metadata().map(|m| m.is_file()).unwrap_or(false);
+                        (Box::new(f), is_file)
+                    }
+                    Err(err) => {
+                        println!("error opening audio source {}: {}", config.source, err);
+                        sleep(Duration::from_secs(5));
+                        continue;
+                    }
+                }
+            };
+            let mut read_any = false;
+            let mut leftover = None;
+            loop {
+                match read_full(&mut input, &mut bytes) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        read_any = true;
+                        let samples = decode(&mut leftover, &bytes[..n]);
+                        if analyzer.push(&samples) {
+                            let mut shared = match levels.lock() {
+                                Ok(guard) => guard,
+                                Err(poisoned) => poisoned.into_inner(),
+                            };
+                            *shared = analyzer.get_levels().clone();
+                        }
+                        if is_file {
+                            sleep(hop_time);
+                        }
+                    }
+                    Err(err) => {
+                        println!("error reading audio source {}: {}", config.source, err);
+                        break;
+                    }
+                }
+            }
+            if config.source == "-" {
+                // stdin has closed for good
+                return;
+            }
+            if !read_any {
+                sleep(Duration::from_secs(1));
+            }
+        }
+    });
+}
+
+// samples from `bytes`, starting with the odd byte held over from the last read, and holding
+// over the odd byte at the end so the next read stays in step
+fn decode(leftover: &mut Option<u8>, bytes: &[u8]) -> Vec<f32> {
+    let mut data: Vec<u8> = leftover.take().into_iter().chain(bytes.iter().copied()).collect();
+    if data.len() % 2 == 1 {
+        *leftover = data.pop();
+    }
+    data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0).collect()
+}
+
+// fills as much of `buf` as the source will give before it ends
+fn read_full(input: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(hz: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * std::f32::consts::PI * hz * i as f32 / 44100.0).sin() * amplitude).collect()
+    }
+
+    #[test]
+    fn test_analyzer_bands() {
+        // -test- a tone lights the band it falls in and not the others
+        let mut analyzer = Analyzer::new(44100, 8);
+        assert!(analyzer.push(&tone(1000.0, 0.5, WINDOW)));
+        let levels = analyzer.get_levels();
+        let loudest = levels.bands.iter().enumerate().fold(0, |best, (i, b)| if *b > levels.bands[best] { i } else { best });
+        assert!(levels.bands[loudest] > 0.85);
+        assert!(levels.bands[0] < 0.5);
+        assert!((levels.level - to_level(0.5)).abs() < 0.02);
+
+        // -test- silence lets the bands fall back
+        for _ in 0..40 {
+            analyzer.push(&vec![0.0; HOP]);
+        }
+        assert!(analyzer.get_levels().bands.iter().all(|b| *b < 0.01));
+        assert_eq!(analyzer.get_levels().level, 0.0);
+    }
+
+    #[test]
+    fn test_beats() {
+        // -test- bass thumps twice a second are counted as beats, a steady tone isn't
+        let mut analyzer = Analyzer::new(44100, 8);
+        analyzer.push(&tone(60.0, 0.05, 44100));
+        assert_eq!(analyzer.get_levels().beats, 0);
+        for _ in 0..4 {
+            analyzer.push(&tone(60.0, 0.8, 4410));
+            analyzer.push(&tone(60.0, 0.05, 17640));
+        }
+        assert_eq!(analyzer.get_levels().beats, 4);
+    }
+
+    #[test]
+    fn test_input() {
+        // -test- bands and bass stay within the FFT at the lowest rate and with the most bands
+        for (rate, bands) in [(MIN_SAMPLE_RATE, 64), (50, 64), (200, 16)] {
+            let mut analyzer = Analyzer::new(rate, bands);
+            assert!(analyzer.edges.iter().all(|e| *e <= WINDOW / 2));
+            assert!(analyzer.bass_bins <= WINDOW / 2);
+            assert!(analyzer.push(&tone(100.0, 0.5, WINDOW)));
+        }
+
+        // -test- a sample split across reads is put back together
+        let mut leftover = None;
+        assert_eq!(decode(&mut leftover, &[0x00, 0x40, 0x00]), [0.5]);
+        assert_eq!(leftover, Some(0x00));
+        assert_eq!(decode(&mut leftover, &[0xC0]), [-0.5]);
+        assert_eq!(leftover, None);
+    }
+}
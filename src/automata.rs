@@ -0,0 +1,154 @@
+// elementary cellular automata along a strip and Conway's Life on a matrix, both with the edges
+// wrapping around
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// generations remembered to spot a Life board that has died out, frozen or settled into a
+// short cycle
+const HISTORY: usize = 12;
+
+#[derive(Debug, Clone, Default)]
+pub struct Automaton {
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+    previous: Vec<bool>,
+    history: Vec<u64>,
+}
+
+impl Automaton {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// How alive cell `i` is, 0.0 to 1.0, `progress` of the way from the last generation to
+    /// this one. Cells are numbered row by row from the top left.
+    pub fn level(&self, i: usize, progress: f32) -> f32 {
+        let now = self.cells.get(i).copied().unwrap_or(false) as u8 as f32;
+        let before = self.previous.get(i).copied().unwrap_or(false) as u8 as f32;
+        before + (now - before) * progress.clamp(0.0, 1.0)
+    }
+
+    /// Starts over on a `width` by `height` board with about a third of the cells alive.
+    pub fn reseed(&mut self, width: usize, height: usize, rng: &mut fastrand::Rng) {
+        self.width = width;
+        self.height = height;
+        self.cells = (0..width * height).map(|_| rng.u8(0..3) == 0).collect();
+        self.previous = self.cells.clone();
+        self.history.clear();
+    }
+
+    /// Advances one generation, by Wolfram `rule` on a single row or by Life on a board. A row
+    /// that stops changing or a Life board that repeats itself is reseeded.
+    pub fn step(&mut self, rule: u8, rng: &mut fastrand::Rng) {
+        let next = if self.height > 1 { self.life() } else { self.elementary(rule) };
+        let stagnant = if self.height > 1 {
+            let hash = hash_cells(&next);
+            let seen = self.history.contains(&hash);
+            self.history.push(hash);
+            if self.history.len() > HISTORY {
+                self.history.remove(0);
+            }
+            seen || !next.contains(&true)
+        } else {
+            next == self.cells
+        };
+        if stagnant {
+            self.reseed(self.width, self.height, rng);
+        } else {
+            self.previous = std::mem::replace(&mut self.cells, next);
+        }
+    }
+
+    fn elementary(&self, rule: u8) -> Vec<bool> {
+        let len = self.cells.len();
+        (0..len)
+            .map(|i| {
+                let left = self.cells[(i + len - 1) % len] as u8;
+                let center = self.cells[i] as u8;
+                let right = self.cells[(i + 1) % len] as u8;
+                (rule >> ((left << 2) | (center << 1) | right)) & 1 == 1
+            })
+            .collect()
+    }
+
+    fn life(&self) -> Vec<bool> {
+        let (w, h) = (self.width, self.height);
+        let mut next = vec![false; w * h];
+        for y in 0..h {
+            for x in 0..w {
+                let mut neighbors = 0;
+                for (dx, dy) in [(w - 1, h - 1), (0, h - 1), (1, h - 1), (w - 1, 0), (1, 0), (w - 1, 1), (0, 1), (1, 1)] {
+                    neighbors += self.cells[((y + dy) % h) * w + (x + dx) % w] as u8;
+                }
+                let alive = self.cells[y * w + x];
+                next[y * w + x] = neighbors == 3 || (alive && neighbors == 2);
+            }
+        }
+        next
+    }
+}
+
+fn hash_cells(cells: &[bool]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    cells.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(width: usize, height: usize, alive: &[usize]) -> Automaton {
+        let mut cells = vec![false; width * height];
+        for i in alive {
+            cells[*i] = true;
+        }
+        Automaton { width, height, previous: cells.clone(), cells, history: Vec::new() }
+    }
+
+    #[test]
+    fn test_elementary() {
+        let mut rng = fastrand::Rng::with_seed(1);
+
+        // -test- rule 90 spreads a single cell both ways, wrapping at the edges
+        let mut row = board(5, 1, &[0]);
+        row.step(90, &mut rng);
+        assert_eq!(row.cells, [false, true, false, false, true]);
+
+        // -test- cells fade from their last state
+        assert_eq!(row.level(0, 0.25), 0.75);
+        assert_eq!(row.level(1, 0.25), 0.25);
+
+        // -test- a row that stops changing is reseeded
+        let mut row = board(8, 1, &[3]);
+        row.step(204, &mut rng);
+        assert_ne!(row.cells, board(8, 1, &[3]).cells);
+    }
+
+    #[test]
+    fn test_life() {
+        let mut rng = fastrand::Rng::with_seed(1);
+
+        // -test- a blinker oscillates, and is reseeded once it repeats
+        let mut life = board(5, 5, &[11, 12, 13]);
+        life.step(0, &mut rng);
+        assert_eq!(life.cells, board(5, 5, &[7, 12, 17]).cells);
+        life.step(0, &mut rng);
+        assert_eq!(life.cells, board(5, 5, &[11, 12, 13]).cells);
+        life.step(0, &mut rng);
+        assert_ne!(life.cells, board(5, 5, &[7, 12, 17]).cells);
+
+        // -test- a glider crosses the wrapped edge
+        let mut life = board(6, 6, &[1, 8, 12, 13, 14]);
+        for _ in 0..24 {
+            life.step(0, &mut rng);
+        }
+        assert_eq!(life.cells, board(6, 6, &[1, 8, 12, 13, 14]).cells);
+    }
+}
@@ -1,19 +1,21 @@
+use std::f32::consts::TAU;
+
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+use crate::color_names;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "ColorInput")]
 pub struct ColorRgb {
     r: u8,
     g: u8,
     b: u8,
 }
 
+#[allow(dead_code)]
 impl ColorRgb {
     pub fn new(r: u8, g: u8, b: u8) -> Self {
-        Self {
-            r: r,
-            g: g,
-            b: b,
-        }
+        Self { r, g, b }
     }
 
     pub fn set_rgb(&mut self, rgb: &ColorRgb) {
@@ -47,19 +49,315 @@ impl ColorRgb {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+/// A color at 16 bits per channel, which keeps fades smooth until the frame is quantized for
+/// the LED chips.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ColorRgb16 {
+    r: u16,
+    g: u16,
+    b: u16,
+}
+
+impl ColorRgb16 {
+    pub fn new(r: u16, g: u16, b: u16) -> Self {
+        Self { r, g, b }
+    }
+
+    pub fn get_r(&self) -> u16 {
+        self.r
+    }
+
+    pub fn get_g(&self) -> u16 {
+        self.g
+    }
+
+    pub fn get_b(&self) -> u16 {
+        self.b
+    }
+
+    /// Rounded down to 8 bits per channel.
+    pub fn to_rgb(self) -> ColorRgb {
+        let eight = |c: u16| (c / 257) as u8;
+        ColorRgb { r: eight(self.r), g: eight(self.g), b: eight(self.b) }
+    }
+}
+
+impl From<ColorRgb> for ColorRgb16 {
+    fn from(color: ColorRgb) -> Self {
+        let sixteen = |c: u8| c as u16 * 257;
+        Self { r: sixteen(color.r), g: sixteen(color.g), b: sixteen(color.b) }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "ColorInput")]
 pub struct ColorHsv {
     h: f32,
     s: f32,
     v: f32,
 }
 
+// range of color temperatures accepted, in Kelvin
+pub const MIN_KELVIN: f32 = 1000.0;
+pub const MAX_KELVIN: f32 = 40000.0;
+
+// the forms a color can be given in: a string such as "#ff8800", "rgb(255, 136, 0)",
+// "hsv(32, 100%, 100%)", "2700K" or a CSS name, or an object with h, s and v, with r, g and b,
+// or with kelvin
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorInput {
+    Text(String),
+    Fields(ColorFields),
+}
+
+#[derive(Deserialize)]
+struct ColorFields {
+    h: Option<Component>,
+    s: Option<Component>,
+    v: Option<Component>,
+    r: Option<f32>,
+    g: Option<f32>,
+    b: Option<f32>,
+    kelvin: Option<f32>,
+}
+
+// an HSV component, as a fraction from 0 to 1 or a string with units such as "30deg" or "50%"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Component {
+    Number(f32),
+    Text(String),
+}
+
+// a parsed color, kept as given so RGB input reaches a `ColorRgb` unchanged
+enum ParsedColor {
+    Rgb(ColorRgb),
+    Hsv(ColorHsv),
+}
+
+fn full() -> f32 {
+    1.0
+}
+
+fn full_current() -> [u8; 3] {
+    [MAX_CURRENT; 3]
+}
+
+impl ColorInput {
+    fn parse(self) -> Result<ParsedColor, String> {
+        match self {
+            ColorInput::Text(text) => parse_text(&text),
+            ColorInput::Fields(fields) => fields.parse(),
+        }
+    }
+}
+
+impl ColorFields {
+    fn parse(self) -> Result<ParsedColor, String> {
+        match self {
+            ColorFields { h: Some(h), s: Some(s), v: Some(v), r: None, g: None, b: None, kelvin: None } => {
+                Ok(ParsedColor::Hsv(ColorHsv { h: h.hue()?, s: s.fraction("s")?, v: v.fraction("v")? }))
+            }
+            ColorFields { h: None, s: None, v: None, r: Some(r), g: Some(g), b: Some(b), kelvin: None } => {
+                Ok(ParsedColor::Rgb(ColorRgb { r: channel("r", r)?, g: channel("g", g)?, b: channel("b", b)? }))
+            }
+            ColorFields { h: None, s: None, v, r: None, g: None, b: None, kelvin: Some(kelvin) } => {
+                let v = v.map_or(Ok(1.0), |v| v.fraction("v"))?;
+                Ok(ParsedColor::Hsv(ColorHsv::from_kelvin(check_kelvin(kelvin)?, v)))
+            }
+            _ => Err(String::from("a color needs h, s and v, or r, g and b, or kelvin")),
+        }
+    }
+}
+
+impl Component {
+    // a fraction of the way round, or degrees in a string with or without a unit, as in `hsv()`
+    fn hue(&self) -> Result<f32, String> {
+        match self {
+            Component::Number(h) if (0.0..=1.0).contains(h) => Ok(*h),
+            Component::Text(text) => Ok(degrees(text)? / 360.0),
+            _ => Err(String::from("h must be from 0 to 1, or given in degrees such as \"30deg\"")),
+        }
+    }
+
+    fn fraction(&self, name: &str) -> Result<f32, String> {
+        match self {
+            Component::Number(c) if (0.0..=1.0).contains(c) => Ok(*c),
+            Component::Text(text) => percent(name, text),
+            _ => Err(format!("{} must be from 0 to 1, or a percentage such as \"50%\"", name)),
+        }
+    }
+}
+
+// `text` as a number from 0 to `max`
+fn number(name: &str, text: &str, max: f32) -> Result<f32, String> {
+    match text.trim().parse::<f32>() {
+        Ok(n) if (0.0..=max).contains(&n) => Ok(n),
+        _ => Err(format!("{} must be a number from 0 to {}, not \"{}\"", name, max, text.trim())),
+    }
+}
+
+// a hue in degrees, "30", "30deg" or "30°"
+fn degrees(text: &str) -> Result<f32, String> {
+    let text = text.trim();
+    number("h", text.strip_suffix("deg").or_else(|| text.strip_suffix('°')).unwrap_or(text), 360.0)
+}
+
+fn percent(name: &str, text: &str) -> Result<f32, String> {
+    match text.trim().strip_suffix('%') {
+        Some(n) => Ok(number(name, n, 100.0)? / 100.0),
+        None => Err(format!("{} must be a percentage such as \"50%\", not \"{}\"", name, text.trim())),
+    }
+}
+
+fn channel(name: &str, c: f32) -> Result<u8, String> {
+    if (0.0..=255.0).contains(&c) {
+        Ok(c.round() as u8)
+    } else {
+        Err(format!("{} must be from 0 to 255", name))
+    }
+}
+
+fn check_kelvin(kelvin: f32) -> Result<f32, String> {
+    if (MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+        Ok(kelvin)
+    } else {
+        Err(format!("kelvin must be between {} and {}", MIN_KELVIN, MAX_KELVIN))
+    }
+}
+
+// the arguments of `name(...)` in `text`, split on commas or spaces
+fn arguments<'a>(text: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let inner = text.strip_prefix(name)?.trim_start().strip_prefix('(')?.strip_suffix(')')?;
+    Some(inner.split(|c: char| c == ',' || c.is_whitespace()).filter(|a| !a.is_empty()).collect())
+}
+
+fn parse_text(text: &str) -> Result<ParsedColor, String> {
+    let text = text.trim().to_ascii_lowercase();
+    if let Some(hex) = text.strip_prefix('#') {
+        let digits: Option<Vec<u8>> = hex.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect();
+        let [r, g, b] = match (digits.as_deref(), hex.len()) {
+            (Some(&[r, g, b]), 3) => [r * 17, g * 17, b * 17],
+            (Some(&[r1, r2, g1, g2, b1, b2]), 6) => [r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2],
+            _ => return Err(format!("invalid hex color \"#{}\", expected #rgb or #rrggbb", hex)),
+        };
+        Ok(ParsedColor::Rgb(ColorRgb { r, g, b }))
+    } else if let Some(args) = arguments(&text, "rgb") {
+        match args[..] {
+            [r, g, b] => Ok(ParsedColor::Rgb(ColorRgb {
+                r: number("r", r, 255.0)?.round() as u8,
+                g: number("g", g, 255.0)?.round() as u8,
+                b: number("b", b, 255.0)?.round() as u8,
+            })),
+            _ => Err(format!("invalid color \"{}\", expected rgb(r, g, b) from 0 to 255", text)),
+        }
+    } else if let Some(args) = arguments(&text, "hsv") {
+        // as in CSS, a bare hue is in degrees and bare saturation and value are percentages
+        match args[..] {
+            [h, s, v] => Ok(ParsedColor::Hsv(ColorHsv {
+                h: degrees(h)? / 360.0,
+                s: number("s", s.trim_end_matches('%'), 100.0)? / 100.0,
+                v: number("v", v.trim_end_matches('%'), 100.0)? / 100.0,
+            })),
+            _ => Err(format!("invalid color \"{}\", expected hsv(h, s%, v%)", text)),
+        }
+    } else if let Some(kelvin) = text.strip_suffix('k').and_then(|k| k.trim().parse::<f32>().ok()) {
+        Ok(ParsedColor::Hsv(ColorHsv::from_kelvin(check_kelvin(kelvin)?, 1.0)))
+    } else {
+        match color_names::lookup(&text) {
+            Some([r, g, b]) => Ok(ParsedColor::Rgb(ColorRgb { r, g, b })),
+            None => Err(format!("unknown color \"{}\", expected a hex code, rgb(), hsv(), a temperature such as \"2700K\" or a CSS color name", text)),
+        }
+    }
+}
+
+impl TryFrom<ColorInput> for ColorHsv {
+    type Error = String;
+
+    fn try_from(input: ColorInput) -> Result<Self, Self::Error> {
+        match input.parse()? {
+            ParsedColor::Rgb(rgb) => Ok(Self::from_srgb([rgb.r, rgb.g, rgb.b].map(|c| c as f32 / 255.0))),
+            ParsedColor::Hsv(hsv) => Ok(hsv),
+        }
+    }
+}
+
+impl TryFrom<ColorInput> for ColorRgb {
+    type Error = String;
+
+    fn try_from(input: ColorInput) -> Result<Self, Self::Error> {
+        match input.parse()? {
+            ParsedColor::Rgb(rgb) => Ok(rgb),
+            ParsedColor::Hsv(hsv) => Ok(hsv.to_rgb()),
+        }
+    }
+}
+
+/// Red, green and blue from 0.0 to 1.0 of white light at `kelvin`, after Tanner Helland's
+/// fit of blackbody colors.
+pub fn kelvin_to_rgb(kelvin: f32) -> [f32; 3] {
+    let t = kelvin.clamp(MIN_KELVIN, MAX_KELVIN) / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.6987 * (t - 60.0).powf(-0.13320476) };
+    let g = if t <= 66.0 { 99.4708 * t.ln() - 161.11957 } else { 288.12217 * (t - 60.0).powf(-0.075514846) };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    [r, g, b].map(|c: f32| c.clamp(0.0, 255.0) / 255.0)
+}
+
+/// The highest drive current setting of HD108 chips.
+pub const MAX_CURRENT: u8 = 31;
+
+/// Per-channel gains that make a device's white match other devices', from the config.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WhiteBalance {
+    #[serde(default = "full")]
+    pub r: f32,
+    #[serde(default = "full")]
+    pub g: f32,
+    #[serde(default = "full")]
+    pub b: f32,
+    // red, green and blue drive current of chips that take one, which scales them without
+    // giving up any of the 16 bits of the channels
+    #[serde(default = "full_current")]
+    pub current: [u8; 3],
+}
+
+impl Default for WhiteBalance {
+    fn default() -> Self {
+        Self { r: 1.0, g: 1.0, b: 1.0, current: full_current() }
+    }
+}
+
+impl WhiteBalance {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if ![self.r, self.g, self.b].iter().all(|c| (0.0..=1.0).contains(c)) {
+            Err("gains must be between 0 and 1")
+        } else if self.current.iter().any(|&c| c > MAX_CURRENT) {
+            Err("current must be between 0 and 31")
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn apply(&self, color: ColorRgb16) -> ColorRgb16 {
+        let gain = |c: u16, g: f32| (c as f32 * g).round() as u16;
+        ColorRgb16 { r: gain(color.r, self.r), g: gain(color.g, self.g), b: gain(color.b, self.b) }
+    }
+}
+
+#[allow(dead_code)]
 impl ColorHsv {
     pub fn new(h: f32, s: f32, v: f32) -> Self {
         Self { 
-            h: h.max(0.0).min(1.0), 
-            s: s.max(0.0).min(1.0), 
-            v: v.max(0.0).min(1.0),
+            h: h.clamp(0.0, 1.0), 
+            s: s.clamp(0.0, 1.0), 
+            v: v.clamp(0.0, 1.0),
         }
     }
 
@@ -74,7 +372,7 @@ impl ColorHsv {
         Self::new(f32::from_le_bytes(h), f32::from_le_bytes(s), f32::from_le_bytes(v))
     }
 
-    pub fn to_le_bytes(&self) -> [u8; 12] {
+    pub fn to_le_bytes(self) -> [u8; 12] {
         let mut out = [0; 12];
         let h = self.h.to_le_bytes();
         let s = self.s.to_le_bytes();
@@ -86,13 +384,13 @@ impl ColorHsv {
     }
 
     pub fn set_hsv(&mut self, hsv: &ColorHsv) {
-        self.h = hsv.h.max(0.0).min(1.0);
-        self.s = hsv.s.max(0.0).min(1.0);
-        self.v = hsv.v.max(0.0).min(1.0);
+        self.h = hsv.h.clamp(0.0, 1.0);
+        self.s = hsv.s.clamp(0.0, 1.0);
+        self.v = hsv.v.clamp(0.0, 1.0);
     }
 
     pub fn set_h(&mut self, h: f32) {
-        self.h = h.max(0.0).min(1.0);
+        self.h = h.clamp(0.0, 1.0);
     }
 
     pub fn get_h(&self) -> f32 {
@@ -100,7 +398,7 @@ impl ColorHsv {
     }
 
     pub fn set_s(&mut self, s: f32) {
-        self.s = s.max(0.0).min(1.0);
+        self.s = s.clamp(0.0, 1.0);
     }
 
     pub fn get_s(&self) -> f32 {
@@ -108,50 +406,70 @@ impl ColorHsv {
     }
 
     pub fn set_v(&mut self, v: f32) {
-        self.v = v.max(0.0).min(1.0);
+        self.v = v.clamp(0.0, 1.0);
     }
 
     pub fn get_v(&self) -> f32 {
         self.v
     }
 
-    pub fn to_rgb(&self) -> ColorRgb {
-        let mut out = ColorRgb { r: 0, g: 0, b: 0 };
-        let h_decimal = (self.h * 6.0) - (((self.h * 6.0) as u8) as f32);
-        match (self.h * 6.0 % 6.0) as u8 {
-            0 => {
-                out.r = (self.v * 255.0) as u8;
-                out.g = ((self.v * (1.0 - self.s * (1.0 - h_decimal))) * 255.0) as u8;
-                out.b = ((self.v * (1.0 - self.s)) * 255.0) as u8;
-            }
-            1 => {
-                out.r = ((self.v * (1.0 - self.s * h_decimal)) * 255.0) as u8;
-                out.g = (self.v * 255.0) as u8;
-                out.b = ((self.v * (1.0 - self.s)) * 255.0) as u8;
-            }
-            2 => {
-                out.r = ((self.v * (1.0 - self.s)) * 255.0) as u8;
-                out.g = (self.v * 255.0) as u8;
-                out.b = ((self.v * (1.0 - self.s * (1.0 - h_decimal))) * 255.0) as u8;
-            }
-            3 => {
-                out.r = ((self.v * (1.0 - self.s)) * 255.0) as u8;
-                out.g = ((self.v * (1.0 - self.s * h_decimal)) * 255.0) as u8;
-                out.b = (self.v * 255.0) as u8;
-            }
-            4 => {
-                out.r = ((self.v * (1.0 - self.s * (1.0 - h_decimal))) * 255.0) as u8;
-                out.g = ((self.v * (1.0 - self.s)) * 255.0) as u8;
-                out.b = (self.v * 255.0) as u8;
-            }
-            5 => {
-                out.r = (self.v * 255.0) as u8;
-                out.g = ((self.v * (1.0 - self.s)) * 255.0) as u8;
-                out.b = ((self.v * (1.0 - self.s * h_decimal)) * 255.0) as u8;
-            }
-            _ => (),
+    /// White light at a color temperature of `kelvin`, `v` bright.
+    pub fn from_kelvin(kelvin: f32, v: f32) -> Self {
+        let mut color = Self::from_srgb(kelvin_to_rgb(kelvin));
+        color.set_v(v);
+        color
+    }
+
+    // red, green and blue from 0.0 to 1.0, taking the color as sRGB the way the UI shows it
+    fn to_srgb(self) -> [f32; 3] {
+        let h = self.h.rem_euclid(1.0) * 6.0;
+        let f = h.fract();
+        let (v, s) = (self.v, self.s);
+        let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+        match h as u8 {
+            0 => [v, t, p],
+            1 => [q, v, p],
+            2 => [p, v, t],
+            3 => [p, q, v],
+            4 => [t, p, v],
+            _ => [v, p, q],
         }
-        out
+    }
+
+    fn to_linear(self) -> [f32; 3] {
+        self.to_srgb().map(srgb_to_linear)
+    }
+
+    fn from_linear(rgb: [f32; 3]) -> Self {
+        Self::from_srgb(rgb.map(linear_to_srgb))
+    }
+
+    fn from_srgb([r, g, b]: [f32; 3]) -> Self {
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let h = if delta <= 0.0 {
+            0.0
+        } else if max == r {
+            ((g - b) / delta).rem_euclid(6.0) / 6.0
+        } else if max == g {
+            ((b - r) / delta + 2.0) / 6.0
+        } else {
+            ((r - g) / delta + 4.0) / 6.0
+        };
+        let s = if max > 0.0 { delta / max } else { 0.0 };
+        Self::new(h, s, max)
+    }
+
+    /// Rounded to 16 bits per channel.
+    pub fn to_rgb16(self) -> ColorRgb16 {
+        let [r, g, b] = self.to_srgb().map(|c| (c.clamp(0.0, 1.0) * 65535.0).round() as u16);
+        ColorRgb16 { r, g, b }
+    }
+
+    /// Rounded to 8 bits per channel.
+    pub fn to_rgb(self) -> ColorRgb {
+        let [r, g, b] = self.to_srgb().map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        ColorRgb { r, g, b }
     }
 }
 
@@ -159,7 +477,7 @@ pub fn hsv_interp(col1: &ColorHsv, col2: &ColorHsv, pos: f32) -> ColorHsv {
     let h_range = col1.h - col2.h;
     let s_range = col1.s - col2.s;
     let v_range = col1.v - col2.v;
-    let pos_clip = pos.max(0.0).min(1.0);
+    let pos_clip = pos.clamp(0.0, 1.0);
 
     let h_out = col1.h - (h_range * pos_clip);
     let s_out = col1.s - (s_range * pos_clip);
@@ -168,16 +486,157 @@ pub fn hsv_interp(col1: &ColorHsv, col2: &ColorHsv, pos: f32) -> ColorHsv {
     ColorHsv { h: h_out, s: s_out, v: v_out }
 }
 
-pub fn hsv_interp_3(col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, pos: f32) -> ColorHsv {
-	let mut pos_clip = 0.0;
-    if pos < 0.0 {
-        pos_clip = pos.max(-1.0);
-    } else if pos > 0.0 {
-        pos_clip = pos.min(1.0);
-    } else {
-        pos_clip = 0.0;
+/// Color space gradients and fades are worked out in, set per device with `interpolation`.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    // hue, saturation and value each straight from one color to the other
+    #[default]
+    Hsv,
+    // as `Hsv`, with hue taking the short way round the color wheel
+    HsvShortest,
+    // red, green and blue mixed in linear light
+    Rgb,
+    // lightness and color mixed in OKLab, which keeps midpoints from going muddy
+    Oklab,
+    // OKLab lightness and chroma, with hue taking the short way round
+    Oklch,
+}
+
+impl Interpolation {
+    /// The color `pos` of the way from `col1` to `col2`.
+    pub fn mix(self, col1: &ColorHsv, col2: &ColorHsv, pos: f32) -> ColorHsv {
+        let pos = pos.clamp(0.0, 1.0);
+        match self {
+            Interpolation::Hsv => hsv_interp(col1, col2, pos),
+            Interpolation::HsvShortest => {
+                // a grey has no hue of its own, so it takes the other color's
+                let h1 = if col1.s == 0.0 || col1.v == 0.0 { col2.h } else { col1.h };
+                let h2 = if col2.s == 0.0 || col2.v == 0.0 { col1.h } else { col2.h };
+                ColorHsv {
+                    h: hue_lerp(h1, h2, pos),
+                    s: lerp(col1.s, col2.s, pos),
+                    v: lerp(col1.v, col2.v, pos),
+                }
+            }
+            Interpolation::Rgb => {
+                let (a, b) = (col1.to_linear(), col2.to_linear());
+                ColorHsv::from_linear([0, 1, 2].map(|c| lerp(a[c], b[c], pos)))
+            }
+            Interpolation::Oklab => {
+                let (a, b) = (oklab(col1.to_linear()), oklab(col2.to_linear()));
+                ColorHsv::from_linear(linear_from_oklab([0, 1, 2].map(|c| lerp(a[c], b[c], pos))))
+            }
+            Interpolation::Oklch => {
+                let ([l1, a1, b1], [l2, a2, b2]) = (oklab(col1.to_linear()), oklab(col2.to_linear()));
+                let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+                let h1 = b1.atan2(a1) / TAU;
+                let h2 = b2.atan2(a2) / TAU;
+                let h1 = if c1 < ACHROMATIC { h2 } else { h1 };
+                let h2 = if c2 < ACHROMATIC { h1 } else { h2 };
+                let (l, c, h) = (lerp(l1, l2, pos), lerp(c1, c2, pos), hue_lerp(h1, h2, pos) * TAU);
+                ColorHsv::from_linear(linear_from_oklab([l, c * h.cos(), c * h.sin()]))
+            }
+        }
+    }
+
+    /// As `hsv_interp_3`: `col1` at 0, toward `col2` up to 1 and toward `col3` down to -1.
+    pub fn mix_3(self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, pos: f32) -> ColorHsv {
+        if self == Interpolation::Hsv {
+            hsv_interp_3(col1, col2, col3, pos)
+        } else if pos < 0.0 {
+            self.mix(col1, col3, -pos)
+        } else {
+            self.mix(col1, col2, pos)
+        }
+    }
+}
+
+// OKLab chroma below which a color counts as grey
+const ACHROMATIC: f32 = 1e-4;
+
+fn lerp(a: f32, b: f32, pos: f32) -> f32 {
+    a + (b - a) * pos
+}
+
+// hues from 0.0 to 1.0, going whichever way round is shorter
+fn hue_lerp(a: f32, b: f32, pos: f32) -> f32 {
+    let mut d = (b - a).rem_euclid(1.0);
+    if d > 0.5 {
+        d -= 1.0;
     }
-	if pos_clip > 0.0 {
+    (a + d * pos).rem_euclid(1.0)
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+// linear sRGB to OKLab, see https://bottosson.github.io/posts/oklab/
+fn oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.4122215 * r + 0.5363325 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806996 * g + 0.107397 * b).cbrt();
+    let s = (0.08830246 * r + 0.2817188 * g + 0.6299787 * b).cbrt();
+    [
+        0.2104543 * l + 0.7936178 * m - 0.00407205 * s,
+        1.977998 * l - 2.428592 * m + 0.4505937 * s,
+        0.02590404 * l + 0.7827718 * m - 0.8086758 * s,
+    ]
+}
+
+fn linear_from_oklab([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.3963378 * a + 0.2158038 * b).powi(3);
+    let m_ = (l - 0.1055613 * a - 0.06385417 * b).powi(3);
+    let s_ = (l - 0.08948418 * a - 1.291486 * b).powi(3);
+    [
+        4.076742 * l_ - 3.307712 * m_ + 0.2309699 * s_,
+        -1.268438 * l_ + 2.609757 * m_ - 0.3413194 * s_,
+        -0.00419609 * l_ - 0.7034186 * m_ + 1.707615 * s_,
+    ]
+}
+
+/// How a layer is combined with the layers below it.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+    Max,
+}
+
+/// Blends `top` over `base` with `mode`, then mixes the result with `base` by `opacity`.
+pub fn blend(base: ColorRgb16, top: ColorRgb16, mode: BlendMode, opacity: f32) -> ColorRgb16 {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let channel = |b: u16, t: u16| {
+        let b = b as f32 / 65535.0;
+        let t = t as f32 / 65535.0;
+        let mixed = match mode {
+            BlendMode::Normal => t,
+            BlendMode::Add => (b + t).min(1.0),
+            BlendMode::Multiply => b * t,
+            BlendMode::Screen => 1.0 - (1.0 - b) * (1.0 - t),
+            BlendMode::Max => b.max(t),
+        };
+        ((b + (mixed - b) * opacity) * 65535.0).round() as u16
+    };
+    ColorRgb16 {
+        r: channel(base.r, top.r),
+        g: channel(base.g, top.g),
+        b: channel(base.b, top.b),
+    }
+}
+
+pub fn hsv_interp_3(col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, pos: f32) -> ColorHsv {
+    let pos_clip = pos.clamp(-1.0, 1.0);
+    if pos_clip > 0.0 {
         let h_range = col1.h - col2.h;
         let s_range = col1.s - col2.s;
         let v_range = col1.v - col2.v;
@@ -192,9 +651,9 @@ pub fn hsv_interp_3(col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, pos: f32)
         let s_range = col1.s - col3.s;
         let v_range = col1.v - col3.v;
         
-        let h_out = col1.h - (h_range * (pos_clip * -1.0));
-        let s_out = col1.s - (s_range * (pos_clip * -1.0));
-        let v_out = col1.v - (v_range * (pos_clip * -1.0));
+        let h_out = col1.h - (h_range * -pos_clip);
+        let s_out = col1.s - (s_range * -pos_clip);
+        let v_out = col1.v - (v_range * -pos_clip);
 
         ColorHsv { h: h_out, s: s_out, v: v_out }
     } else {
@@ -251,4 +710,122 @@ mod tests {
         assert_eq!(hsv_interp_3(&col_hsv_h50, &col_hsv_h100, &col_hsv_h0, -0.5), col_hsv_h25);
         assert_eq!(hsv_interp_3(&col_hsv_h50, &col_hsv_h100, &col_hsv_h0, -1.0), col_hsv_h0);
     }
+
+    #[test]
+    fn test_interpolation() {
+        let magenta = ColorHsv::new(0.95, 1.0, 1.0);
+        let orange = ColorHsv::new(0.05, 1.0, 1.0);
+        let red = ColorHsv::new(0.0, 1.0, 1.0);
+        let blue = ColorHsv::new(2.0 / 3.0, 1.0, 1.0);
+        let white = ColorHsv::new(0.0, 0.0, 1.0);
+        let close = |a: ColorRgb, b: ColorRgb| [(a.r, b.r), (a.g, b.g), (a.b, b.b)].iter().all(|(x, y)| x.abs_diff(*y) <= 2);
+
+        // -test- plain HSV sweeps the whole wheel, the shortest hue crosses red
+        assert!((Interpolation::Hsv.mix(&magenta, &orange, 0.5).get_h() - 0.5).abs() < 0.001);
+        assert!(Interpolation::HsvShortest.mix(&magenta, &orange, 0.5).get_h() < 0.001);
+        assert_eq!(Interpolation::HsvShortest.mix(&white, &blue, 0.5).get_h(), blue.get_h());
+
+        // -test- every space starts and ends on the given colors
+        for space in [Interpolation::Hsv, Interpolation::HsvShortest, Interpolation::Rgb, Interpolation::Oklab, Interpolation::Oklch] {
+            assert!(close(space.mix(&red, &blue, 0.0).to_rgb(), red.to_rgb()), "{:?}", space);
+            assert!(close(space.mix(&red, &blue, 1.0).to_rgb(), blue.to_rgb()), "{:?}", space);
+        }
+
+        // -test- linear light keeps the red to blue midpoint brighter than plain RGB averaging
+        let mid = Interpolation::Rgb.mix(&red, &blue, 0.5).to_rgb();
+        assert!(mid.r > 127 && mid.r == mid.b && mid.g == 0);
+        let mid = Interpolation::Oklab.mix(&red, &blue, 0.5).to_rgb();
+        assert!(mid.r > mid.g && mid.b > mid.g);
+        assert!(Interpolation::Oklch.mix(&white, &blue, 0.5).get_s() < 1.0);
+
+        // -test- the three color form goes toward the third color below 0
+        assert_eq!(Interpolation::Hsv.mix_3(&red, &blue, &white, -1.0), white);
+    }
+
+    #[test]
+    fn test_kelvin() {
+        // -test- warm white is orange, 6600K is about white and hotter is blue
+        let warm = ColorHsv::from_kelvin(2700.0, 1.0).to_rgb();
+        assert_eq!(warm.r, 255);
+        assert!(warm.g > 150 && warm.g < 180 && warm.b > 70 && warm.b < 100);
+        let white = ColorHsv::from_kelvin(6600.0, 1.0).to_rgb();
+        assert!(white.r == 255 && white.g > 245 && white.b > 245);
+        let cold = ColorHsv::from_kelvin(20000.0, 1.0).to_rgb();
+        assert!(cold.b == 255 && cold.r < 200);
+        assert_eq!(ColorHsv::from_kelvin(2700.0, 0.5).get_v(), 0.5);
+
+        // -test- colors can be sent as a temperature, within range
+        let parsed: ColorHsv = serde_json::from_str(r#"{"kelvin": 2700, "v": 0.5}"#).unwrap();
+        assert_eq!(parsed, ColorHsv::from_kelvin(2700.0, 0.5));
+        assert!(serde_json::from_str::<ColorHsv>(r#"{"kelvin": 100}"#).is_err());
+        let hsv: ColorHsv = serde_json::from_str(r#"{"h": 0.5, "s": 1.0, "v": 1.0}"#).unwrap();
+        assert_eq!(hsv, ColorHsv::new(0.5, 1.0, 1.0));
+
+        // -test- white balance scales each channel
+        let balance = WhiteBalance { r: 1.0, g: 0.9, b: 0.5, ..Default::default() };
+        assert_eq!(balance.apply(ColorRgb16 { r: 65535, g: 20000, b: 1000 }), ColorRgb16 { r: 65535, g: 18000, b: 500 });
+        assert!(WhiteBalance { r: 1.5, ..Default::default() }.validate().is_err());
+        assert!(WhiteBalance { current: [31, 32, 31], ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_color_input() {
+        let hsv = |json: &str| serde_json::from_str::<ColorHsv>(json);
+        let rgb = |json: &str| serde_json::from_str::<ColorRgb>(json).unwrap();
+        let orange = ColorRgb::new(255, 136, 0);
+
+        // -test- hex, rgb(), RGB objects and CSS names all give the same color
+        assert_eq!(rgb(r##""#ff8800""##), orange);
+        assert_eq!(rgb(r##""#F80""##), orange);
+        assert_eq!(rgb(r#""rgb(255, 136, 0)""#), orange);
+        assert_eq!(rgb(r#"{"r": 255, "g": 136, "b": 0}"#), orange);
+        assert_eq!(rgb(r#""DarkOrange""#), ColorRgb::new(255, 140, 0));
+        assert_eq!(hsv(r##""#ff8800""##).unwrap().to_rgb(), orange);
+        assert_eq!(hsv(r##""#3a7bd5""##).unwrap().to_rgb(), ColorRgb::new(0x3a, 0x7b, 0xd5));
+        assert_eq!(hsv(r#""red""#).unwrap(), ColorHsv::new(0.0, 1.0, 1.0));
+
+        // -test- RGB input is converted exactly, so it keeps its level at 16 bits
+        assert_eq!(hsv(r##""#808080""##).unwrap().to_rgb16(), ColorRgb16::new(32896, 32896, 32896));
+        assert_eq!(hsv(r##""#808080""##).unwrap().to_rgb(), ColorRgb::new(128, 128, 128));
+
+        // -test- HSV in degrees and percentages, and temperatures as strings
+        let cyan = ColorHsv::new(0.5, 1.0, 0.5);
+        assert_eq!(hsv(r#"{"h": "180deg", "s": "100%", "v": "50%"}"#).unwrap(), cyan);
+        assert_eq!(hsv(r#""hsv(180, 100%, 50%)""#).unwrap(), cyan);
+        assert_eq!(hsv(r#"{"h": "180", "s": "100%", "v": "50%"}"#).unwrap(), cyan);
+        assert_eq!(hsv(r#"{"h": 0.5, "s": 1, "v": 0.5}"#).unwrap(), cyan);
+        assert_eq!(hsv(r#""2700K""#).unwrap(), ColorHsv::from_kelvin(2700.0, 1.0));
+        assert_eq!(serde_json::to_string(&orange).unwrap(), r#"{"r":255,"g":136,"b":0}"#);
+
+        // -test- bad input says what is wrong
+        let error = |json: &str| hsv(json).unwrap_err().to_string();
+        assert!(error(r##""#ff88""##).contains("expected #rgb or #rrggbb"));
+        assert!(error(r#""blurple""#).contains("unknown color \"blurple\""));
+        assert!(error(r#""rgb(300, 0, 0)""#).contains("r must be a number from 0 to 255"));
+        assert!(error(r#"{"h": 2, "s": 1, "v": 1}"#).contains("h must be from 0 to 1"));
+        assert!(error(r#"{"h": "50%", "s": 1, "v": 1}"#).contains("h must be a number from 0 to 360"));
+        assert!(error(r#""hsv(50%, 100%, 50%)""#).contains("h must be a number from 0 to 360"));
+        assert!(error(r#"{"h": 0.5, "s": "150%", "v": 1}"#).contains("s must be a number from 0 to 100"));
+        assert!(error(r#"{"r": 255, "g": 0}"#).contains("a color needs"));
+        assert!(error(r#""500K""#).contains("kelvin must be between"));
+    }
+
+    #[test]
+    fn test_blend() {
+        let base = ColorRgb::new(200, 100, 0);
+        let top = ColorRgb::new(100, 200, 255);
+        let mix = |mode, opacity| blend(base.into(), top.into(), mode, opacity).to_rgb();
+
+        // -test- each blend mode at full opacity
+        assert_eq!(mix(BlendMode::Normal, 1.0), top);
+        assert_eq!(mix(BlendMode::Add, 1.0), ColorRgb::new(255, 255, 255));
+        assert_eq!(mix(BlendMode::Multiply, 1.0), ColorRgb::new(78, 78, 0));
+        assert_eq!(mix(BlendMode::Screen, 1.0), ColorRgb::new(221, 221, 255));
+        assert_eq!(mix(BlendMode::Max, 1.0), ColorRgb::new(200, 200, 255));
+
+        // -test- opacity mixes the blended color with the base, keeping the bits below 8
+        assert_eq!(mix(BlendMode::Normal, 0.0), base);
+        assert_eq!(mix(BlendMode::Normal, 0.5), ColorRgb::new(150, 150, 127));
+        assert_eq!(blend(base.into(), top.into(), BlendMode::Normal, 0.5).get_b(), 32768);
+    }
 }
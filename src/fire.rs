@@ -0,0 +1,75 @@
+// heat simulation after Mark Kriegsman's Fire2012: every frame each cell cools a little, heat
+// drifts up and diffuses, and new sparks ignite near the bottom
+
+#[derive(Debug, Clone, Default)]
+pub struct Fire {
+    heat: Vec<u8>,
+}
+
+impl Fire {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Heat of each cell from the bottom of the flame up, 0 is cold and 255 hottest.
+    pub fn get_heat(&self) -> &[u8] {
+        &self.heat
+    }
+
+    /// Advances the simulation one frame. `cooling` (20-100) sets how fast cells lose heat and
+    /// so how tall the flames are, `sparking` (50-200) is the chance out of 255 of a new spark.
+    pub fn step(&mut self, len: usize, cooling: u8, sparking: u8, rng: &mut fastrand::Rng) {
+        if self.heat.len() != len {
+            self.heat = vec![0; len];
+        }
+        if len == 0 {
+            return;
+        }
+
+        let max_cooling = ((cooling as usize * 10) / len + 2).min(255) as u8;
+        for h in self.heat.iter_mut() {
+            *h = h.saturating_sub(rng.u8(0..=max_cooling));
+        }
+
+        for k in (2..len).rev() {
+            self.heat[k] = ((self.heat[k - 1] as u16 + 2 * self.heat[k - 2] as u16) / 3) as u8;
+        }
+
+        if rng.u8(..) < sparking {
+            let y = rng.usize(0..len.min(7));
+            self.heat[y] = self.heat[y].saturating_add(rng.u8(160..=255));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fire() {
+        // -test- the same seed gives the same flames
+        let mut a = Fire::new();
+        let mut b = Fire::new();
+        let mut rng_a = fastrand::Rng::with_seed(7);
+        let mut rng_b = fastrand::Rng::with_seed(7);
+        for _ in 0..100 {
+            a.step(30, 55, 120, &mut rng_a);
+            b.step(30, 55, 120, &mut rng_b);
+        }
+        assert_eq!(a.get_heat(), b.get_heat());
+        assert!(a.get_heat().iter().any(|h| *h > 0));
+
+        // -test- without sparks the fire burns out
+        for _ in 0..500 {
+            a.step(30, 55, 0, &mut rng_a);
+        }
+        assert!(a.get_heat().iter().all(|h| *h == 0));
+
+        // -test- resizing and empty strips are handled
+        a.step(5, 55, 120, &mut rng_a);
+        assert_eq!(a.get_heat().len(), 5);
+        a.step(0, 55, 120, &mut rng_a);
+        assert!(a.get_heat().is_empty());
+    }
+}
@@ -1,291 +1,596 @@
-use crate::colors::{hsv_interp, ColorRgb, ColorHsv};
-use crate::sprites::Sprite;
+use crate::automata::Automaton;
+use crate::colors::{blend, BlendMode, ColorRgb, ColorRgb16, ColorHsv, Interpolation, WhiteBalance};
+use crate::font;
+use crate::maps::{Matrix, PixelMap, Point};
+use crate::noise::NoiseField;
+use crate::palettes::Palette;
+use crate::sprites::{Ball, Comet, Sprite};
+use crate::twinkle::Twinkles;
+
+// where the base of the flames sits on the strip
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FireLayout {
+    // rising from the first LED
+    Bottom,
+    // rising from the last LED
+    Top,
+    // rising from both ends to meet in the middle
+    Mirrored,
+    // rising from the middle toward both ends
+    Centered,
+}
 
 #[derive (Clone)]
 pub enum LedType {
     Apa102,
     Ws2801,
+    // 16 bits per channel, with a 5-bit drive current for each
+    Hd108,
 }
 
 pub struct Leds {
     led_type: LedType,
     len: usize,
-    //~ buffer: [u8; B],
     buffer: Vec<u8>,
+    // last color written to each LED at 16 bits per channel, read back when compositing
+    // layers and quantized into `buffer` by `encode`
+    pixels: Vec<ColorRgb16>,
+    // with temporal dithering, the part of each channel below one 8-bit step that is owed to
+    // the next frame
+    dither: bool,
+    error: Vec<[f32; 3]>,
+    // color space of gradients and fades, set from the device state
+    interpolation: Interpolation,
+    // channel gains applied on the way out, so they don't compound when layers are blended
+    white_balance: WhiteBalance,
 }
 
 impl Leds {
     pub fn new(len: usize, led_type: LedType) -> Self {
-        let mut buffer_size: usize = 0;
-        match led_type {
-            LedType::Apa102 => { buffer_size = 4 + (len * 4) + ((len + 1) / 2); }
-            LedType::Ws2801 => { buffer_size = len * 3; }
-        }
-        let mut buffer = Vec::with_capacity(buffer_size);
-        for _i in 0..buffer_size {
-            buffer.push(0);
-        }
-        buffer.iter_mut().for_each(|v| *v = 0 );
+        let buffer_size = match led_type {
+            LedType::Apa102 => 4 + (len * 4) + len.div_ceil(2),
+            LedType::Ws2801 => len * 3,
+            LedType::Hd108 => 16 + (len * 8) + len.div_ceil(2),
+        };
         Self {
-            led_type: led_type,
-            len: len,
-            //~ buffer: [0; B],
-            buffer: buffer,
+            led_type,
+            len,
+            buffer: vec![0; buffer_size],
+            pixels: vec![ColorRgb16::default(); len],
+            dither: false,
+            error: vec![[0.0; 3]; len],
+            interpolation: Interpolation::default(),
+            white_balance: WhiteBalance::default(),
         }
     }
 
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    pub fn set_white_balance(&mut self, white_balance: WhiteBalance) {
+        self.white_balance = white_balance;
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    /// The color `pos` of the way from `col1` to `col2` in the device's color space.
+    pub fn mix(&self, col1: &ColorHsv, col2: &ColorHsv, pos: f32) -> ColorHsv {
+        self.interpolation.mix(col1, col2, pos)
+    }
+
     pub fn all_off(&mut self) {
-        match self.led_type {
-            LedType::Apa102 => {
-                let len = self.len;
-                self.buffer.chunks_mut(4).skip(1).enumerate().for_each(|(i, v)| {
-                    if i < len {
-                        v[0] = 255;
-                        v[1] = 0;
-                        v[2] = 0;
-                        v[3] = 0;
-                    }
-                });
-            }
-            LedType::Ws2801 => {
-                self.buffer.iter_mut().for_each(|v| *v = 0 );
-            }
+        for i in 0..self.len {
+            self.set_led(ColorRgb::new(0, 0, 0), i);
         }
     }
-    
-    pub fn get_buffer(&self) -> &[u8] {
-        &self.buffer
-    }
-    
-    pub fn set_led(&mut self, color: ColorRgb, index: usize) {
-        if index < self.len {
+
+    /// Encodes the frame for the LED chips, quantizing it to 8 bits per channel for those that
+    /// take no more. With dithering each channel's rounding error is carried into the next
+    /// frame, so levels between two steps show as a flicker too fast to see.
+    pub fn encode(&mut self) -> &[u8] {
+        for i in 0..self.len {
+            let color = self.white_balance.apply(self.pixels[i]);
             match self.led_type {
                 LedType::Apa102 => {
-                    match self.buffer.chunks_mut(4).skip(1).nth(index) {
-                        Some(v) => {
-                            v[0] = 255;
-                            v[1] = color.get_b();
-                            v[2] = color.get_g();
-                            v[3] = color.get_r();
-                        },
-                        None => {},
+                    let color = self.quantize(color, i);
+                    if let Some(v) = self.buffer.chunks_mut(4).nth(i + 1) {
+                        v[0] = 255;
+                        v[1] = color.get_b();
+                        v[2] = color.get_g();
+                        v[3] = color.get_r();
                     }
                 }
                 LedType::Ws2801 => {
-                    match self.buffer.chunks_mut(3).nth(index) {
-                        Some(v) => {
-                            v[0] = color.get_r();
-                            v[1] = color.get_g();
-                            v[2] = color.get_b();
-                        },
-                        None => {},
+                    let color = self.quantize(color, i);
+                    if let Some(v) = self.buffer.chunks_mut(3).nth(i) {
+                        v[0] = color.get_r();
+                        v[1] = color.get_g();
+                        v[2] = color.get_b();
+                    }
+                }
+                LedType::Hd108 => {
+                    // a start bit and the red, green and blue currents, then the channels
+                    // big-endian
+                    let [r, g, b] = self.white_balance.current.map(|c| c as u16);
+                    let header = 0x8000 | (r << 10) | (g << 5) | b;
+                    if let Some(v) = self.buffer[16..].chunks_mut(8).nth(i) {
+                        for (bytes, word) in v.chunks_mut(2).zip([header, color.get_r(), color.get_g(), color.get_b()]) {
+                            bytes.copy_from_slice(&word.to_be_bytes());
+                        }
                     }
                 }
             }
         }
+        &self.buffer
+    }
+
+    // `color` at 8 bits per channel for the LED at `index`
+    fn quantize(&mut self, color: ColorRgb16, index: usize) -> ColorRgb {
+        if !self.dither {
+            return color.to_rgb();
+        }
+        let error = &mut self.error[index];
+        let mut channel = |n: usize, c: u16| {
+            let target = c as f32 / 257.0 + error[n];
+            let level = target.floor().min(255.0);
+            error[n] = target - level;
+            level as u8
+        };
+        ColorRgb::new(channel(0, color.get_r()), channel(1, color.get_g()), channel(2, color.get_b()))
+    }
+
+    // the quantized color of an LED, as it shows without dithering
+    #[allow(dead_code)]
+    pub fn get_led(&self, index: usize) -> Option<ColorRgb> {
+        self.pixels.get(index).map(|c| c.to_rgb())
+    }
+
+    pub fn get_pixel(&self, index: usize) -> Option<ColorRgb16> {
+        self.pixels.get(index).copied()
+    }
+
+    pub fn get_pixels(&self) -> &[ColorRgb16] {
+        &self.pixels
+    }
+
+    pub fn set_led(&mut self, color: ColorRgb, index: usize) {
+        self.set_pixel(ColorRgb16::from(color), index);
+    }
+
+    pub fn set_pixel(&mut self, color: ColorRgb16, index: usize) {
+        if let Some(pixel) = self.pixels.get_mut(index) {
+            *pixel = color;
+        }
+    }
+
+    // position of an LED along the strip, from 0.0 at the first LED to 1.0 at the last
+    fn position(&self, index: usize) -> f32 {
+        if self.len > 1 {
+            (index as f32) / ((self.len - 1) as f32)
+        } else {
+            0.0
+        }
     }
 
+    pub fn fill_solid(&mut self, col: &ColorHsv) {
+        let rgb = col.to_rgb16();
+        for i in 0..self.len {
+            self.set_pixel(rgb, i);
+        }
+    }
+
+    // copies `pixels` onto the strip, leaving any LEDs past their end off
+    pub fn fill_pixels(&mut self, pixels: &[ColorRgb16]) {
+        for i in 0..self.len {
+            self.set_pixel(pixels.get(i).copied().unwrap_or_default(), i);
+        }
+    }
+
+    // `col1` on every `spacing`th pixel counting from `step`, `col2` between them
+    pub fn fill_theater(&mut self, col1: &ColorHsv, col2: &ColorHsv, step: i64, spacing: usize) {
+        let (on, off) = (col1.to_rgb16(), col2.to_rgb16());
+        for i in 0..self.len {
+            let lit = (i as i64 - step).rem_euclid(spacing as i64) == 0;
+            self.set_pixel(if lit { on } else { off }, i);
+        }
+    }
+
+    // `to` covering the first `progress` of the strip, or the last if `reverse`, `from` after it
+    pub fn fill_wipe(&mut self, from: &ColorHsv, to: &ColorHsv, progress: f32, reverse: bool) {
+        let (from, to) = (from.to_rgb16(), to.to_rgb16());
+        for i in 0..self.len {
+            let pos = if reverse { 1.0 - self.position(i) } else { self.position(i) };
+            self.set_pixel(if pos < progress { to } else { from }, i);
+        }
+    }
+
+    #[allow(dead_code)]
     pub fn fill_gradient(&mut self, start: &ColorHsv, end: &ColorHsv) {
-        match self.led_type {
-            LedType::Apa102 => {
-                let len = self.len;
-                self.buffer.chunks_mut(4).skip(1).enumerate().for_each(|(i, v)| {
-                    if i < len {
-                        let pos = (i as f32) / ((len - 1) as f32);
-                        let rgb = hsv_interp(&start, &end, pos).to_rgb();
-                        v[0] = 255;
-                        v[1] = rgb.get_b();
-                        v[2] = rgb.get_g();
-                        v[3] = rgb.get_r();
-                    }
-                });
-            }
-            LedType::Ws2801 => {
-                self.buffer.chunks_mut(3).enumerate().for_each(|(i, v)| {
-                    let pos = (i as f32) / ((self.len - 1) as f32);
-                    let rgb = hsv_interp(&start, &end, pos).to_rgb();
-                    v[0] = rgb.get_r();
-                    v[1] = rgb.get_g();
-                    v[2] = rgb.get_b();
-                });
-            }
+        for i in 0..self.len {
+            let pos = self.position(i);
+            let rgb = self.mix(start, end, pos).to_rgb16();
+            self.set_pixel(rgb, i);
         }
     }
 
+    #[allow(dead_code)]
     pub fn fill_gradient_dual(&mut self, start: &ColorHsv, end: &ColorHsv) {
-        match self.led_type {
-            LedType::Apa102 => {
-                self.buffer.chunks_mut(4).skip(1).enumerate().for_each(|(i, v)| {
-                    if i < self.len {
-                        let pos = (i as f32) / ((self.len - 1) as f32);
-                        let mut pos_bipolar = pos * 2.0 - 1.0;
-                        if pos_bipolar < 0.0 { pos_bipolar = pos_bipolar * -1.0 }
-                        let rgb = hsv_interp(&end, &start, pos_bipolar).to_rgb();
-                        v[0] = 255;
-                        v[1] = rgb.get_b();
-                        v[2] = rgb.get_g();
-                        v[3] = rgb.get_r();
-                    }
-                });
-            }
-            LedType::Ws2801 => {                
-                self.buffer.chunks_mut(3).enumerate().for_each(|(i, v)| {
-                    let pos = (i as f32) / ((self.len - 1) as f32);
-                    let mut pos_bipolar = pos * 2.0 - 1.0;
-                    if pos_bipolar < 0.0 { pos_bipolar = pos_bipolar * -1.0 }
-                    let rgb = hsv_interp(&end, &start, pos_bipolar).to_rgb();
-                    v[0] = rgb.get_r();
-                    v[1] = rgb.get_g();
-                    v[2] = rgb.get_b();
-                });
-            }
+        for i in 0..self.len {
+            let pos_bipolar = (self.position(i) * 2.0 - 1.0).abs();
+            let rgb = self.mix(end, start, pos_bipolar).to_rgb16();
+            self.set_pixel(rgb, i);
         }
     }
-    
+
     pub fn fill_gradient_triple(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv) {
-        match self.led_type {
-            LedType::Apa102 => {
-                self.buffer.chunks_mut(4).skip(1).enumerate().for_each(|(i, v)| {
-                    if i < self.len {
-                        let pos = (i as f32) / ((self.len - 1) as f32);
-                        let mut pos_bipolar = pos * 2.0 - 1.0;
-                        let mut rgb = ColorRgb::new(0, 0, 0);
-                        if pos_bipolar < 0.0 {
-                            pos_bipolar = pos_bipolar * -1.0;
-                            rgb = hsv_interp(&col2, &col1, pos_bipolar).to_rgb();
-                        } else {
-                            rgb = hsv_interp(&col2, &col3, pos).to_rgb();
-                        }
-                        //~ let rgb = hsv_interp(&end, &start, pos_bipolar).to_rgb();
-                        v[0] = 255;
-                        v[1] = rgb.get_b();
-                        v[2] = rgb.get_g();
-                        v[3] = rgb.get_r();
-                    }
-                });
-            }
-            LedType::Ws2801 => {                
-                self.buffer.chunks_mut(3).enumerate().for_each(|(i, v)| {
-                    let pos = (i as f32) / ((self.len - 1) as f32);
-                    let mut pos_bipolar = pos * 2.0 - 1.0;
-                    let mut rgb = ColorRgb::new(0, 0, 0);
-                    if pos_bipolar < 0.0 {
-                        pos_bipolar = pos_bipolar * -1.0;
-                        rgb = hsv_interp(&col2, &col1, pos_bipolar).to_rgb();
-                    } else {
-                        rgb = hsv_interp(&col2, &col3, pos).to_rgb();
-                    }
-                    v[0] = rgb.get_r();
-                    v[1] = rgb.get_g();
-                    v[2] = rgb.get_b();
-                });
-            }
+        for i in 0..self.len {
+            let pos = self.position(i);
+            let pos_bipolar = pos * 2.0 - 1.0;
+            let rgb = if pos_bipolar < 0.0 {
+                self.mix(col2, col1, -pos_bipolar).to_rgb16()
+            } else {
+                self.mix(col2, col3, pos).to_rgb16()
+            };
+            self.set_pixel(rgb, i);
         }
     }
-    
-    pub fn fill_sine(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, phase: f32) {
-        match self.led_type {
-            LedType::Apa102 => {
-                self.buffer.chunks_mut(4).skip(1).enumerate().for_each(|(i, v)| {
-                    if i < self.len {
-                        let pos = (i as f32) / ((self.len - 1) as f32);
-                        let pos_triangle = (((pos + phase) % 1.0) * 2.0 - 1.0).abs() * 2.0 - 1.0;
-                        let mut rgb = ColorRgb::new(0, 0, 0);
-                        if pos_triangle < 0.0 {
-                            rgb = hsv_interp(&col1, &col2, pos_triangle * -1.0).to_rgb();
-                        } else {
-                            rgb = hsv_interp(&col1, &col3, pos_triangle).to_rgb();
-                        }
-                        v[0] = 255;
-                        v[1] = rgb.get_b();
-                        v[2] = rgb.get_g();
-                        v[3] = rgb.get_r();
-                    }
-                });
-            }
-            LedType::Ws2801 => {
-                self.buffer.chunks_mut(3).enumerate().for_each(|(i, v)| {
-                    let pos = (i as f32) / ((self.len - 1) as f32);
-                    let pos_triangle = (((pos + phase) % 1.0) * 2.0 - 1.0).abs() * 2.0 - 1.0;
-                    let mut rgb = ColorRgb::new(0, 0, 0);
-                    if pos_triangle < 0.0 {
-                        rgb = hsv_interp(&col1, &col2, pos_triangle * -1.0).to_rgb();
-                    } else {
-                        rgb = hsv_interp(&col1, &col3, pos_triangle).to_rgb();
-                    }
-                    v[0] = rgb.get_r();
-                    v[1] = rgb.get_g();
-                    v[2] = rgb.get_b();
-                });
-            }
+
+    pub fn fill_sine(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, phase: f32, scale: f32) {
+        for i in 0..self.len {
+            let rgb = triangle_color(self.interpolation, col1, col2, col3, self.position(i) * scale + phase);
+            self.set_pixel(rgb, i);
+        }
+    }
+
+    // the palette stretched `scale` times along the strip
+    pub fn fill_palette(&mut self, palette: &Palette, phase: f32, scale: f32) {
+        for i in 0..self.len {
+            let rgb = palette.at(self.position(i) * scale + phase, self.interpolation).to_rgb16();
+            self.set_pixel(rgb, i);
         }
     }
 
-    pub fn fill_sprites(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, sprites: &Vec<Sprite>) {
-        let mut sprite_values = Vec::with_capacity(self.len);
-        for _ in 0..self.len { sprite_values.push(0.0) }
-        //for sprite in sprites {
-        for (i, v) in sprite_values.iter_mut().enumerate() {
+    pub fn fill_sprites(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, sprites: &[Sprite]) {
+        for i in 0..self.len {
+            let pos = self.position(i);
             let mut value_total = 0.0;
-            let pos = (i as f32) / ((self.len - 1) as f32);
             for sprite in sprites {
                 let delta = (pos - sprite.get_pos()).abs();
                 let value = (1.0 - (delta * sprite.get_falloff())).max(0.0) * 1.5;
                 value_total = (value_total + value.min(1.0)).min(1.0);
             }
-            *v = value_total;
+            let pos_bipolar = (pos * 2.0 - 1.0).abs();
+            let gradient = self.mix(col2, col1, pos_bipolar);
+            let rgb = self.mix(&gradient, col3, value_total).to_rgb16();
+            self.set_pixel(rgb, i);
         }
-        match self.led_type {
-            LedType::Apa102 => {
-                self.buffer.chunks_mut(4).skip(1).enumerate().filter(|(i, _v)| { i < &self.len }).for_each(|(i, v)| {
-                    let pos = (i as f32) / ((self.len - 1) as f32);
-                    let mut pos_bipolar = pos * 2.0 - 1.0;
-                    if pos_bipolar < 0.0 { pos_bipolar = pos_bipolar * -1.0 }
-                    let gradient = hsv_interp(&col2, &col1, pos_bipolar);
-                    let rgb = hsv_interp(&gradient, &col3, sprite_values[i]).to_rgb();
-                    v[0] = 255;
-                    v[1] = rgb.get_b();
-                    v[2] = rgb.get_g();
-                    v[3] = rgb.get_r();
-                });
+    }
+
+    // the nearest comet head or tail covering each pixel, tails dimming by `(1 - d)^decay`
+    // along their length
+    pub fn fill_comets(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, comets: &[Comet], decay: f32) {
+        let head = 1.0 / self.len.max(1) as f32;
+        for i in 0..self.len {
+            let pos = self.position(i);
+            let nearest = comets
+                .iter()
+                .filter_map(|c| c.trail(pos).map(|d| (d, d * c.get_length())))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let color = match nearest {
+                Some((_, behind)) if behind < head => *col1,
+                Some((d, _)) => {
+                    let mut tail = self.mix(col2, col3, d);
+                    tail.set_v(tail.get_v() * (1.0 - d).powf(decay));
+                    tail
+                }
+                None => {
+                    let mut black = *col1;
+                    black.set_v(0.0);
+                    black
+                }
+            };
+            self.set_pixel(color.to_rgb16(), i);
+        }
+    }
+
+    // a blob `1 / falloff` wide around each ball, the brightest ball winning where they overlap
+    pub fn fill_balls(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, balls: &[Ball], falloff: f32) {
+        let cols = [col1, col2, col3];
+        for i in 0..self.len {
+            let pos = self.position(i);
+            let mut black = *col1;
+            black.set_v(0.0);
+            let mut color = black;
+            let mut brightest = 0.0;
+            for (n, ball) in balls.iter().enumerate() {
+                let value = (1.0 - (pos - ball.get_pos()).abs() * falloff).max(0.0);
+                if value > brightest {
+                    brightest = value;
+                    let mut dark = *cols[n % 3];
+                    dark.set_v(0.0);
+                    color = self.mix(&dark, cols[n % 3], value);
+                }
             }
-            LedType::Ws2801 => {
-                self.buffer.chunks_mut(3).enumerate().for_each(|(i, v)| {
-                    let pos = (i as f32) / ((self.len - 1) as f32);
-                    let mut pos_bipolar = pos * 2.0 - 1.0;
-                    if pos_bipolar < 0.0 { pos_bipolar = pos_bipolar * -1.0 }
-                    //let rgb = hsv_interp(&col2, &col1, pos_bipolar).to_rgb();
-                    let gradient = hsv_interp(&col2, &col1, pos_bipolar);
-                    let rgb = hsv_interp(&gradient, &col3, sprite_values[i]).to_rgb();
-                    v[0] = rgb.get_r();
-                    v[1] = rgb.get_g();
-                    v[2] = rgb.get_b();
-                });
+            self.set_pixel(color.to_rgb16(), i);
+        }
+    }
+
+    // each cell of the automaton on its LED, through the matrix layout if there is one
+    pub fn fill_automaton(&mut self, col1: &ColorHsv, col2: &ColorHsv, automaton: &Automaton, progress: f32, map: &PixelMap) {
+        let (width, height) = automaton.size();
+        for cell in 0..width * height {
+            let i = match map.get_matrix() {
+                Some(matrix) => matrix.index(cell % width, cell / width),
+                None => cell,
+            };
+            if i < self.len {
+                let rgb = self.mix(col1, col2, automaton.level(cell, progress)).to_rgb16();
+                self.set_pixel(rgb, i);
             }
         }
     }
 
-    /*
-    pub fn fill_random(&mut self, start: &ColorHsv, end: &ColorHsv, sprites: &RandomSprites<{ NUM_LEDS }>) {
-        match self.led_type {
-            LedType::Apa102 => {
-                self.buffer.chunks_mut(4).skip(1).zip(sprites.get_sprites()).for_each(|(led, sprite)| {
-                    let rgb = hsv_interp(&end, &start, sprite.get_value()).to_rgb();
-                    led[0] = 255;
-                    led[1] = rgb.get_b();
-                    led[2] = rgb.get_g();
-                    led[3] = rgb.get_r();
-                });
+    // hands at positions around a ring, where 1.0 is all the way round. On a strip each lights
+    // the one or two LEDs nearest to it, on a map with area each is a line out from the center
+    pub fn fill_hands(&mut self, hands: &[(ColorHsv, f32)], map: &PixelMap) {
+        let len = self.len as f32;
+        let polar = map.polar();
+        for i in 0..self.len {
+            let mut rgb = ColorRgb16::default();
+            for (color, pos) in hands {
+                // how far the LED is from the hand, in LEDs
+                let distance = match polar.as_ref().and_then(|p| p.get(i)) {
+                    // hands are rays from the center of a map with area
+                    Some((turn, radius)) => {
+                        let delta = (turn - pos).rem_euclid(1.0);
+                        let delta = delta.min(1.0 - delta);
+                        if delta < 0.25 { radius * (delta * std::f32::consts::TAU).sin() } else { *radius }
+                    }
+                    // and points around the ring of a strip
+                    None => {
+                        let delta = (i as f32 / len - pos).rem_euclid(1.0);
+                        delta.min(1.0 - delta) * len
+                    }
+                };
+                let mut hand = *color;
+                hand.set_v(hand.get_v() * (1.0 - distance).max(0.0));
+                rgb = blend(rgb, hand.to_rgb16(), BlendMode::Max, 1.0);
             }
-            LedType::Ws2801 => {                
-                self.buffer.chunks_mut(3).zip(sprites.get_sprites()).for_each(|(led, sprite)| {
-                    let rgb = hsv_interp(&end, &start, sprite.get_value()).to_rgb();
-                    led[0] = rgb.get_r();
-                    led[1] = rgb.get_g();
-                    led[2] = rgb.get_b();
-                });
+            self.set_pixel(rgb, i);
+        }
+    }
+
+    // the first `progress` of the strip lit in a gradient running col1 -> col2 -> col3, with
+    // the LED at the end partly lit
+    pub fn fill_bar(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, progress: f32) {
+        let filled = progress * self.len as f32;
+        for i in 0..self.len {
+            let pos = self.position(i);
+            let mut color = if pos < 0.5 {
+                self.mix(col1, col2, pos * 2.0)
+            } else {
+                self.mix(col2, col3, pos * 2.0 - 1.0)
+            };
+            color.set_v(color.get_v() * (filled - i as f32).clamp(0.0, 1.0));
+            self.set_pixel(color.to_rgb16(), i);
+        }
+    }
+
+    // LEDs up to `level` of the way along lit in the color of their zone, split at the
+    // `thresholds`, plus the LED at `peak`. Filled from the last LED if `reverse`.
+    pub fn fill_meter(&mut self, cols: &[ColorHsv; 3], level: f32, peak: Option<f32>, thresholds: (f32, f32), reverse: bool) {
+        let len = self.len as f32;
+        let peak_led = peak.filter(|p| *p > 0.0).map(|p| ((p * len).ceil() as usize).clamp(1, self.len) - 1);
+        for n in 0..self.len {
+            let i = if reverse { self.len - 1 - n } else { n };
+            // the zone is picked by the middle of the LED's share of the bar
+            let pos = (n as f32 + 0.5) / len;
+            let mut color = if pos < thresholds.0 {
+                cols[0]
+            } else if pos < thresholds.1 {
+                cols[1]
+            } else {
+                cols[2]
+            };
+            if peak_led != Some(n) {
+                color.set_v(color.get_v() * (level * len - n as f32).clamp(0.0, 1.0));
+            }
+            self.set_pixel(color.to_rgb16(), i);
+        }
+    }
+
+    // the strip split into one segment per band, each as bright as its band, in colors
+    // running col1 -> col2 -> col3 from the first band to the last
+    pub fn fill_bands(&mut self, cols: &[ColorHsv; 3], bands: &[f32]) {
+        let count = bands.len().max(1);
+        for i in 0..self.len {
+            let band = (i * count / self.len.max(1)).min(count - 1);
+            let mut color = band_color(self.interpolation, cols, band, count);
+            color.set_v(color.get_v() * bands.get(band).copied().unwrap_or(0.0));
+            self.set_pixel(color.to_rgb16(), i);
+        }
+    }
+
+    // one bar per band across the columns of the matrix, rising from the bottom row
+    pub fn fill_bars(&mut self, cols: &[ColorHsv; 3], bands: &[f32], matrix: &Matrix) {
+        let count = bands.len().max(1);
+        for x in 0..matrix.width {
+            let band = (x * count / matrix.width.max(1)).min(count - 1);
+            let color = band_color(self.interpolation, cols, band, count);
+            let height = bands.get(band).copied().unwrap_or(0.0) * matrix.height as f32;
+            for y in 0..matrix.height {
+                let mut pixel = color;
+                pixel.set_v(pixel.get_v() * (height - (matrix.height - 1 - y) as f32).clamp(0.0, 1.0));
+                let i = matrix.index(x, y);
+                if i < self.len {
+                    self.set_pixel(pixel.to_rgb16(), i);
+                }
+            }
+        }
+    }
+
+    // font columns in col1 over col2, entering from the right edge and `scroll` pixels along,
+    // centered on the rows of the matrix; a strip without one shows the middle row of the font
+    pub fn fill_text(&mut self, col1: &ColorHsv, col2: &ColorHsv, columns: &[u8], scroll: usize, map: &PixelMap) {
+        let matrix = map.get_matrix().copied().unwrap_or(Matrix { width: self.len, height: 1, serpentine: false });
+        let top = (matrix.height as i64 - font::GLYPH_HEIGHT as i64) / 2;
+        let (on, off) = (col1.to_rgb16(), col2.to_rgb16());
+        for x in 0..matrix.width {
+            let column = (x + scroll).checked_sub(matrix.width).and_then(|c| columns.get(c)).copied().unwrap_or(0);
+            for y in 0..matrix.height {
+                let row = y as i64 - top;
+                let lit = (0..font::GLYPH_HEIGHT as i64).contains(&row) && column & (1 << row) != 0;
+                let i = matrix.index(x, y);
+                if i < self.len {
+                    self.set_pixel(if lit { on } else { off }, i);
+                }
             }
         }
     }
-    */
+
+    // heat from black through col1, col2 and col3 as it rises from 0 to 255
+    pub fn fill_fire(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, heat: &[u8], layout: FireLayout) {
+        let mut black = *col1;
+        black.set_v(0.0);
+        let len = self.len;
+        for i in 0..len {
+            let cell = match layout {
+                FireLayout::Bottom => i,
+                FireLayout::Top => len - 1 - i,
+                FireLayout::Mirrored => i.min(len - 1 - i),
+                FireLayout::Centered => (len / 2).abs_diff(i),
+            };
+            let pos = heat.get(cell).copied().unwrap_or(0) as f32 / 255.0 * 3.0;
+            let rgb = if pos < 1.0 {
+                self.mix(&black, col1, pos)
+            } else if pos < 2.0 {
+                self.mix(col1, col2, pos - 1.0)
+            } else {
+                self.mix(col2, col3, pos - 2.0)
+            };
+            self.set_pixel(rgb.to_rgb16(), i);
+        }
+    }
+
+    // each pixel faded from black to the color its twinkle picked
+    pub fn fill_twinkle(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, twinkles: &Twinkles) {
+        let cols = [col1, col2, col3];
+        for i in 0..self.len {
+            let (level, color) = twinkles.get(i);
+            let mut black = *cols[color];
+            black.set_v(0.0);
+            self.set_pixel(self.mix(&black, cols[color], level).to_rgb16(), i);
+        }
+    }
+
+    // bands of color sweeping through the map in the direction of `wave`, with as many bands
+    // across the map as its length
+    pub fn fill_plane(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, map: &PixelMap, wave: Point, phase: f32) {
+        let normal = wave.normalized();
+        let scale = wave.dot(wave).sqrt();
+        let (min, max) = map.extent_along(normal);
+        let range = (max - min).max(f32::EPSILON);
+        for (i, point) in map.get_points().iter().enumerate().take(self.len) {
+            let dist = (point.dot(normal) - min) / range;
+            let rgb = triangle_color(self.interpolation, col1, col2, col3, dist * scale + phase);
+            self.set_pixel(rgb, i);
+        }
+    }
+
+    // rings of color radiating outward from the center of the map
+    pub fn fill_sphere(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, map: &PixelMap, phase: f32, scale: f32) {
+        let center = map.center();
+        let radius = map.radius().max(f32::EPSILON);
+        for (i, point) in map.get_points().iter().enumerate().take(self.len) {
+            let dist = point.distance(center) / radius;
+            // subtracting the phase moves the rings away from the center
+            let rgb = triangle_color(self.interpolation, col1, col2, col3, dist * scale - phase + 1.0);
+            self.set_pixel(rgb, i);
+        }
+    }
+
+    // 3D noise sampled at each LED's coordinate, drifting with `time`
+    pub fn fill_noise(&mut self, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, map: &PixelMap, field: &NoiseField) {
+        for (i, point) in map.get_points().iter().enumerate().take(self.len) {
+            let rgb = self.interpolation.mix_3(col1, col2, col3, field.at(*point) * 1.5).to_rgb16();
+            self.set_pixel(rgb, i);
+        }
+    }
+
+    // the noise picks a position in the palette, mostly from its middle
+    pub fn fill_noise_palette(&mut self, palette: &Palette, map: &PixelMap, field: &NoiseField) {
+        for (i, point) in map.get_points().iter().enumerate().take(self.len) {
+            let rgb = palette.at(field.at(*point) * 0.75 + 0.5, self.interpolation).to_rgb16();
+            self.set_pixel(rgb, i);
+        }
+    }
+}
+
+// color of band `band` out of `count`, running col1 -> col2 -> col3
+fn band_color(interpolation: Interpolation, cols: &[ColorHsv; 3], band: usize, count: usize) -> ColorHsv {
+    let pos = if count > 1 { band as f32 / (count - 1) as f32 } else { 0.0 };
+    if pos < 0.5 {
+        interpolation.mix(&cols[0], &cols[1], pos * 2.0)
+    } else {
+        interpolation.mix(&cols[1], &cols[2], pos * 2.0 - 1.0)
+    }
+}
+
+// maps a position onto a repeating triangle wave that runs col1 -> col2 -> col1 -> col3 -> col1
+fn triangle_color(interpolation: Interpolation, col1: &ColorHsv, col2: &ColorHsv, col3: &ColorHsv, pos: f32) -> ColorRgb16 {
+    let pos_triangle = ((pos.rem_euclid(1.0)) * 2.0 - 1.0).abs() * 2.0 - 1.0;
+    if pos_triangle < 0.0 {
+        interpolation.mix(col1, col2, -pos_triangle).to_rgb16()
+    } else {
+        interpolation.mix(col1, col3, pos_triangle).to_rgb16()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_white_balance() {
+        let mut led = Leds::new(1, LedType::Ws2801);
+        let white = ColorHsv::new(0.0, 0.0, 1.0);
+
+        // -test- calibration scales the output but not the frame layers are blended into
+        led.set_white_balance(WhiteBalance { b: 0.5, ..Default::default() });
+        led.fill_solid(&white);
+        assert_eq!(led.get_led(0), Some(white.to_rgb()));
+        assert_eq!(led.encode(), &[255, 255, 127]);
+    }
+
+    #[test]
+    fn test_dither() {
+        let mut led = Leds::new(1, LedType::Ws2801);
+        led.fill_solid(&ColorHsv::new(0.0, 0.0, 100.25 / 255.0));
+
+        // -test- without dithering channels round down
+        assert_eq!(led.encode()[0], 100);
+
+        // -test- with dithering a level between two 8-bit steps averages out over frames
+        led.set_dither(true);
+        let frames: Vec<u8> = (0..8).map(|_| led.encode()[0]).collect();
+        assert!(frames.iter().all(|&c| c == 100 || c == 101));
+        assert!((801..=802).contains(&frames.iter().map(|&c| c as u32).sum::<u32>()));
+    }
+
+    #[test]
+    fn test_hd108() {
+        let mut led = Leds::new(1, LedType::Hd108);
+        let grey = ColorHsv::new(0.0, 0.0, 100.25 / 255.0);
+        let [hi, lo] = grey.to_rgb16().get_r().to_be_bytes();
+
+        // -test- HD108 chips get the full 16 bits after the start frame, behind their currents
+        led.set_white_balance(WhiteBalance { current: [31, 16, 1], ..Default::default() });
+        led.fill_solid(&grey);
+        let buffer = led.encode();
+        assert!(buffer[..16].iter().all(|&b| b == 0));
+        assert_eq!(&buffer[16..24], &[0xFE, 0x01, hi, lo, hi, lo, hi, lo]);
+    }
 }
@@ -1,26 +1,68 @@
-//~ use std::collections::HashMap;
+use std::collections::HashMap;
 use std::{env, process};
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::ErrorKind;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, sleep};
 use std::time::{Duration, Instant};
 
-use fastrand;
+use chrono::Local;
 use serde::{Serialize, Deserialize};
 //~ use serde_derive::{Deserialize, Serialize};
 use spidev::{Spidev, SpidevOptions, SpiModeFlags};
 use warp::Filter;
 
+mod animation;
+use animation::{Animation, Frame};
+
+mod alerts;
+use alerts::{Alert, AlertMode, AlertQueue, TextMessage};
+
+mod audio;
+use audio::{AudioConfig, AudioLevels};
+
+mod automata;
+
+mod color_names;
+
 mod colors;
-use colors::{ColorRgb, ColorHsv};
+use colors::{blend, BlendMode, ColorHsv, ColorRgb16, Interpolation, WhiteBalance};
+
+mod fire;
+
+mod font;
 
 mod leds;
 use leds::{Leds, LedType};
 
+mod maps;
+use maps::{Matrix, PixelMap};
+
+mod media;
+use media::MediaLibrary;
+
+mod meter;
+
+mod noise;
+
+mod palettes;
+use palettes::{Palette, Palettes};
+
+mod params;
+use params::{ParamValue, Params};
+
+mod patterns;
+use patterns::PatternInfo;
+
+mod plugins;
+use plugins::Plugins;
+
+mod scripts;
+use scripts::Scripts;
+
 mod sprites;
-use sprites::Sprite;
+
+mod twinkle;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct LedState {
@@ -28,6 +70,35 @@ struct LedState {
     color2: Option<ColorHsv>,
     color3: Option<ColorHsv>,
     pattern: Option<u8>,
+    // patterns composited on top of the base pattern, bottom layer first
+    #[serde(default)]
+    layers: Option<Vec<Layer>>,
+    #[serde(default)]
+    params: Option<Params>,
+    // color space of gradients and fades, plain HSV if not set
+    #[serde(default)]
+    interpolation: Option<Interpolation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Layer {
+    #[serde(default)]
+    color1: ColorHsv,
+    #[serde(default)]
+    color2: ColorHsv,
+    #[serde(default)]
+    color3: ColorHsv,
+    pattern: u8,
+    #[serde(default)]
+    params: Params,
+    #[serde(default)]
+    blend: BlendMode,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+}
+
+fn default_opacity() -> f32 {
+    1.0
 }
 
 impl LedState {
@@ -37,31 +108,43 @@ impl LedState {
             color2: Some(ColorHsv::new(0.0, 0.0, 0.0)),
             color3: Some(ColorHsv::new(0.0, 0.0, 0.0)),
             pattern: Some(0),
+            layers: Some(Vec::new()),
+            params: Some(Params::new()),
+            interpolation: None,
         }
     }
 
-    fn serialize(&self) -> [u8; 37] {
-        let mut out = [0; 37];
-        
-        if self.color1.is_some() {
-            let c1 = self.color1.unwrap().to_le_bytes();
-            out.iter_mut().take(12).zip(c1).for_each(|(v, c)| *v = c );
-        }
-        if self.color2.is_some() {
-            let c2 = self.color2.unwrap().to_le_bytes();
-            out.iter_mut().skip(12).take(12).zip(c2).for_each(|(v, c)| *v = c );
+    fn colors(&self) -> [ColorHsv; 3] {
+        [
+            self.color1.unwrap_or_default(),
+            self.color2.unwrap_or_default(),
+            self.color3.unwrap_or_default(),
+        ]
+    }
+
+    fn load(index: usize) -> Self {
+        if let Ok(raw) = fs::read_to_string(format!("state.{}.json", index)) {
+            if let Ok(state) = serde_json::from_str::<LedState>(&raw) {
+                return state;
+            }
         }
-        if self.color3.is_some() {
-            let c3 = self.color3.unwrap().to_le_bytes();
-            out.iter_mut().skip(24).take(12).zip(c3).for_each(|(v, c)| *v = c );
+        // fall back to the fixed-size binary format used by earlier versions
+        if let Ok(mut file) = File::open(format!("state.{}", index)) {
+            let mut buf = [0; 37];
+            if file.read_exact(&mut buf).is_ok() {
+                return LedState::deserialize(buf);
+            }
         }
-        if self.pattern.is_some() {
-            out[36] = self.pattern.unwrap();
+        LedState::new()
+    }
+
+    fn save(&self, index: usize) {
+        if let Ok(json) = serde_json::to_string(self) {
+            let _ = fs::write(format!("state.{}.json", index), json);
         }
-        
-        out
     }
 
+    // reads the 37 byte state files written by earlier versions
     fn deserialize(bytes: [u8; 37]) -> Self {
         let mut c1 = [0; 12];
         let mut c2 = [0; 12];
@@ -75,14 +158,35 @@ impl LedState {
             color2: Some(ColorHsv::from_le_bytes(c2)),
             color3: Some(ColorHsv::from_le_bytes(c3)),
             pattern: Some(bytes[36]),
+            layers: Some(Vec::new()),
+            params: Some(Params::new()),
+            interpolation: None,
         }
     }
 }
 
-fn brightness_adjust(leds: &LedState, brightness: f32) -> [ColorHsv; 3] {
-    let mut col1 = leds.color1.unwrap();
-    let mut col2 = leds.color2.unwrap();
-    let mut col3 = leds.color3.unwrap();
+impl Layer {
+    fn colors(&self) -> [ColorHsv; 3] {
+        [self.color1, self.color2, self.color3]
+    }
+
+    // the alert's pattern as a layer, faded in and out by `level`
+    fn from_alert(alert: &Alert, level: f32) -> Self {
+        let [color1, color2, color3] = alert.colors();
+        Self {
+            color1,
+            color2,
+            color3,
+            pattern: alert.pattern.unwrap_or(1),
+            params: alert.params.clone(),
+            blend: BlendMode::Normal,
+            opacity: level,
+        }
+    }
+}
+
+fn brightness_adjust(colors: [ColorHsv; 3], brightness: f32) -> [ColorHsv; 3] {
+    let [mut col1, mut col2, mut col3] = colors;
 
     col1.set_v(col1.get_v() * brightness);
     col2.set_v(col2.get_v() * brightness);
@@ -90,6 +194,31 @@ fn brightness_adjust(leds: &LedState, brightness: f32) -> [ColorHsv; 3] {
     [col1, col2, col3]
 }
 
+// renders a layer on its own and blends it into the frame already in `led`
+fn render_layer(led: &mut Leds, layer: &Layer, params: &Params, palettes: &Palettes, brightness: f32, map: &PixelMap, anim: &Animation) {
+    let frame: Vec<ColorRgb16> = led.get_pixels().to_vec();
+    let cols = brightness_adjust(layer.colors(), brightness);
+    let palette = palettes.resolve(params).scaled(brightness);
+    patterns::render(led, layer.pattern, &cols, &palette, params, map, anim);
+    for (i, pixel) in frame.into_iter().enumerate() {
+        if let Some(top) = led.get_pixel(i) {
+            led.set_pixel(blend(pixel, top, layer.blend, layer.opacity), i);
+        }
+    }
+}
+
+// checks that `pattern` exists and declares every param in `params`, and that a palette,
+// script or media they name exists
+fn validate_pattern(patterns: &[PatternInfo], palettes: &Palettes, scripts: &Scripts, media: &MediaLibrary, pattern: u8, params: Option<&Params>) -> Result<(), String> {
+    match patterns::find(patterns, pattern) {
+        Some(info) => match params {
+            Some(p) => info.validate(p).and_then(|_| palettes.check(p)).and_then(|_| scripts.check(p)).and_then(|_| media.check(p)),
+            None => Ok(()),
+        },
+        None => Err(format!("invalid pattern {}", pattern)),
+    }
+}
+
 fn wait_for_file(file_name: &str, timeout: u64) -> bool {
     let start = Instant::now();
     let mut result = false;
@@ -116,6 +245,16 @@ fn wait_for_file(file_name: &str, timeout: u64) -> bool {
     result
 }
 
+// a reading for the meter pattern, `value` between `min` and `max` (0 and 1 if not given)
+#[derive(Debug, Clone, Deserialize)]
+struct MeterReading {
+    value: f32,
+    #[serde(default)]
+    min: Option<f32>,
+    #[serde(default)]
+    max: Option<f32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Success<'a> {
     msg: &'a str,
@@ -131,6 +270,22 @@ struct Config {
     main: Main,
     patterns: Patterns,
     rand: Rand,
+    // pixel map file per device, keyed by device index
+    #[serde(default)]
+    maps: HashMap<String, String>,
+    // row and column layout of devices that are LED matrices, keyed by device index
+    #[serde(default)]
+    matrix: HashMap<String, Matrix>,
+    // user palettes by name, each a list of colors
+    #[serde(default)]
+    palettes: HashMap<String, Palette>,
+    // red, green and blue gains per device, keyed by device index, to match whites across
+    // strips from different batches
+    #[serde(default)]
+    calibration: HashMap<String, WhiteBalance>,
+    // PCM input for the sound-reactive patterns
+    #[serde(default)]
+    audio: Option<AudioConfig>,
 }
 
 #[derive(Deserialize)]
@@ -142,11 +297,12 @@ struct Main {
     port: u16,
     spi_devices: Vec<String>,
     devices: Vec<String>,
+    #[serde(default)]
+    dither: bool,
 }
 
 #[derive(Deserialize)]
 struct Patterns {
-    names: Vec<String>,
     scroll_speed: f32,
 }
 
@@ -175,24 +331,119 @@ async fn main() {
     //~ let led_state = Arc::new(Mutex::new(LedState::new()));
     let led_state: Arc<Mutex<Vec<LedState>>> = Arc::new(Mutex::new(Vec::new()));
     for (index, _device) in config.main.devices.iter().enumerate() {
-        if let Ok(mut file) = File::open(format!("state.{}", index)) {
-            let mut buf = [0; 37];
-            if let Ok(_) = file.read_exact(&mut buf) {
-                let state = LedState::deserialize(buf);
-                led_state.lock().unwrap().push(state);
-            } else {
-                led_state.lock().unwrap().push(LedState::new());
+        led_state.lock().unwrap().push(LedState::load(index));
+    }
+    
+    let mut maps = Vec::new();
+    for (index, count) in config.main.led_count.iter().enumerate() {
+        let matrix = config.matrix.get(&index.to_string()).copied();
+        if let Some(m) = matrix {
+            if m.len() != *count {
+                println!("error in matrix config: {} by {} matrix but device {} has {} LEDs", m.width, m.height, index, count);
+                process::exit(1);
             }
-        } else {
-            led_state.lock().unwrap().push(LedState::new());
         }
+        let map = match config.maps.get(&index.to_string()) {
+            Some(path) => {
+                let map = PixelMap::load(path).unwrap_or_else(|err| {
+                    println!("error loading map: {}", err);
+                    process::exit(1);
+                });
+                if map.len() != *count {
+                    println!("error loading map: {} has {} points but device {} has {} LEDs", path, map.len(), index, count);
+                    process::exit(1);
+                }
+                match matrix {
+                    Some(m) => map.with_matrix(m),
+                    None => map,
+                }
+            }
+            None => match matrix {
+                Some(m) => PixelMap::grid(m),
+                None => PixelMap::linear(*count),
+            },
+        };
+        maps.push(map);
     }
-    
+
+    let mut white_balance = Vec::new();
+    for index in 0..config.main.led_count.len() {
+        let balance = config.calibration.get(&index.to_string()).copied().unwrap_or_default();
+        if let Err(err) = balance.validate() {
+            println!("error in calibration config: device {}: {}", index, err);
+            process::exit(1);
+        }
+        white_balance.push(balance);
+    }
+
+    let mut palettes = Palettes::new();
+    for (name, palette) in config.palettes {
+        if let Err(err) = palettes.set(&name, palette) {
+            println!("error loading palette {}: {}", name, err);
+            process::exit(1);
+        }
+    }
+    palettes.load();
+    let palettes = Arc::new(Mutex::new(palettes));
+    let palettes_inner = palettes.clone();
+    let palettes = warp::any().map(move || palettes.clone());
+
+    let mut scripts = Scripts::new();
+    scripts.load();
+    let scripts = Arc::new(Mutex::new(scripts));
+    let scripts_inner = scripts.clone();
+    let scripts = warp::any().map(move || scripts.clone());
+
+    let mut media = MediaLibrary::new();
+    media.load();
+    let media = Arc::new(Mutex::new(media));
+    let media_inner = media.clone();
+    let media = warp::any().map(move || media.clone());
+
+    let audio_levels = Arc::new(Mutex::new(AudioLevels::default()));
+    if let Some(audio) = config.audio {
+        if !(audio::MIN_SAMPLE_RATE..=audio::MAX_SAMPLE_RATE).contains(&audio.sample_rate) {
+            println!("error in audio config: sample_rate must be between {} and {}", audio::MIN_SAMPLE_RATE, audio::MAX_SAMPLE_RATE);
+            process::exit(1);
+        }
+        audio::spawn(audio, audio_levels.clone());
+    }
+
+    let alerts: Arc<Mutex<Vec<AlertQueue>>> = Arc::new(Mutex::new(
+        config.main.devices.iter().map(|_| AlertQueue::new()).collect()
+    ));
+
     let led_state_inner = led_state.clone();
+    let alerts_inner = alerts.clone();
+    // width of each device for scrolling text, if it is a matrix
+    let text_widths: Vec<Option<usize>> = maps.iter().map(|map| map.get_matrix().map(|m| m.width)).collect();
+    let text_widths = warp::any().map(move || text_widths.clone());
+    let alerts = warp::any().map(move || alerts.clone());
     let led_state = warp::any().map(move || led_state.clone());
     //~ let spi_device = config.main.spi_device.clone();
 
-    let patterns = warp::any().map(move || config.patterns.names.clone());
+    // params not set on a device fall back to the values in the config file, which only cover
+    // the scrolling patterns and random sprites
+    let scroll_defaults = Params::new()
+        .with("speed", ParamValue::Number(config.patterns.scroll_speed));
+    let sprite_defaults = Params::new()
+        .with("count", ParamValue::Number(config.rand.count as f32))
+        .with("falloff", ParamValue::Number(config.rand.falloff))
+        .with("max_speed", ParamValue::Number(config.rand.max_speed));
+    let mut pattern_list = patterns::builtin();
+    for info in pattern_list.iter_mut() {
+        if info.is_scrolling() {
+            info.set_defaults(&scroll_defaults);
+        } else if info.id == 3 {
+            info.set_defaults(&sprite_defaults);
+        }
+    }
+    let plugins = Plugins::load();
+    pattern_list.extend(plugins.patterns().cloned());
+    let plugins = Arc::new(plugins);
+    let pattern_list = Arc::new(pattern_list);
+    let pattern_list_inner = pattern_list.clone();
+    let patterns = warp::any().map(move || pattern_list.clone());
     let devices = warp::any().map(move || config.main.devices.clone());
     //let brightness = warp::any().map(move || config.main.brightness.clone());
     
@@ -200,21 +451,25 @@ async fn main() {
         let led_type = match config.main.led_type {
             0 => LedType::Apa102,
             1 => LedType::Ws2801,
+            2 => LedType::Hd108,
             _ => LedType::Apa102,
         };
         //let mut leds_1 = Leds::new(config.main.led_count, led_type);
-        let mut offset: f32 = 0.0;
-        let mut sprites = Vec::with_capacity(config.rand.count);
-        for _ in 0..config.rand.count {
-            let pos = fastrand::f32();
-            let speed = ((fastrand::f32() * 2.0) - 1.0) * config.rand.max_speed;
-            sprites.push(Sprite::new(pos, config.rand.falloff, speed, config.rand.max_speed));
-        }
-        //println!("sprites: {:?}", &sprites);
+        // the params of `pattern` with its defaults filled in
+        let resolve = |pattern: u8, set: &Params| match patterns::find(&pattern_list_inner, pattern) {
+            Some(info) => info.resolve(set),
+            None => Params::new(),
+        };
+        // animation of the base pattern followed by one per layer, for each device
+        let mut anims: Vec<Vec<Animation>> = config.main.led_count.iter().map(|_| vec![Animation::new()]).collect();
+        let mut alert_anims: Vec<Animation> = config.main.led_count.iter().map(|_| Animation::new()).collect();
         let mut leds = Vec::new();
-        for count in config.main.led_count {
+        for (count, balance) in config.main.led_count.into_iter().zip(white_balance) {
             //let mut leds_1 = Leds::new(count, led_type);
-            leds.push(Leds::new(count, led_type.clone()));
+            let mut led = Leds::new(count, led_type.clone());
+            led.set_white_balance(balance);
+            led.set_dither(config.main.dither);
+            leds.push(led);
         }
         let mut spi_devs = Vec::new();
         for i in config.main.spi_devices {
@@ -261,62 +516,80 @@ async fn main() {
         loop {
             //~ println!("LED state: {:?}", led_state_inner);
             
+            // copies of what the REST handlers change, so their locks aren't held while the
+            // frame is drawn and scripts run
             let leds_data = match led_state_inner.lock() {
+                Ok(guard) => guard.clone(),
+                Err(poisoned) => poisoned.into_inner().clone(),
+            };
+            let now = Instant::now();
+            let alerts_data: Vec<_> = match alerts_inner.lock() {
                 Ok(guard) => guard,
                 Err(poisoned) => poisoned.into_inner(),
+            }
+            .iter_mut()
+            .map(|alerts| alerts.current(now).map(|(alert, level, restarted)| (alert.clone(), level, restarted)))
+            .collect();
+            let palettes_data = match palettes_inner.lock() {
+                Ok(guard) => guard.clone(),
+                Err(poisoned) => poisoned.into_inner().clone(),
+            };
+            let period = Duration::from_secs_f32(config.main.secs_per_update);
+            match scripts_inner.lock() {
+                Ok(mut guard) => guard.start_frame(period),
+                Err(poisoned) => poisoned.into_inner().start_frame(period),
+            }
+            let frame = Frame {
+                dt: config.main.secs_per_update,
+                now: Local::now().naive_local(),
+                audio: match audio_levels.lock() {
+                    Ok(guard) => guard.clone(),
+                    Err(poisoned) => poisoned.into_inner().clone(),
+                },
+                brightness: config.main.brightness,
+                scripts: Some(scripts_inner.clone()),
+                plugins: Some(plugins.clone()),
+                media: Some(media_inner.clone()),
             };
-            for ((led_data, mut led), mut spi) in leds_data.iter().zip(&mut leds).zip(&mut spi_devs) {
-                match led_data.pattern {
-                    // all LEDs off
-                    Some(0) => {
+            let devices = leds_data.iter().zip(&mut leds).zip(&mut spi_devs).zip(&maps).zip(alerts_data).zip(&mut anims).zip(&mut alert_anims);
+            for ((((((led_data, led), spi), map), alert), anims), alert_anim) in devices {
+                if matches!(alert, Some((_, _, true))) {
+                    *alert_anim = Animation::new();
+                }
+                led.set_interpolation(led_data.interpolation.unwrap_or_default());
+                if let Some(pattern) = led_data.pattern {
+                    let layers = led_data.layers.as_deref().unwrap_or(&[]);
+                    anims.resize_with(layers.len() + 1, Animation::new);
+                    let params = resolve(pattern, led_data.params.as_ref().unwrap_or(&Params::new()));
+                    if alert.as_ref().is_some_and(|(a, _, _)| a.mode == AlertMode::Replace) {
                         led.all_off();
-                        spi.iter_mut().for_each(|i| {
-                            let _ = i.write(&led.get_buffer());
-                        });
-                    },
-                    // fixed gradient
-                    Some(1) => {
-                        let cols = brightness_adjust(&led_data, config.main.brightness);
-                        led.fill_gradient_triple(&cols[0], &cols[1], &cols[2]);
-                        spi.iter_mut().for_each(|i| {
-                            let _ = i.write(&led.get_buffer());
-                        });
-                    },
-                    // scrolling sine wave
-                    Some(2) => {
-                        let cols = brightness_adjust(&led_data, config.main.brightness);
-                        led.fill_sine(&cols[0], &cols[1], &cols[2], offset);
-                        spi.iter_mut().for_each(|i| {
-                            let _ = i.write(&led.get_buffer());
-                        });
-                    },
-                    // random sprites
-                    Some(3) => {
-                        let cols = brightness_adjust(&led_data, config.main.brightness);
-                        led.fill_sprites(&cols[0], &cols[1], &cols[2], &sprites);
-                        spi.iter_mut().for_each(|i| {
-                            let _ = i.write(&led.get_buffer());
-                        });
-                    },
-                    Some(4) => {
-                        println!("pattern4");
-                        //~ let _ = spi.write(&leds_1.get_buffer());
-                    },
-                    Some(5) => {
-                        println!("pattern5");
-                        //~ let _ = spi.write(&leds_1.get_buffer());
-                    },
-                    _ => (),
+                    } else {
+                        let cols = brightness_adjust(led_data.colors(), config.main.brightness);
+                        let palette = palettes_data.resolve(&params).scaled(config.main.brightness);
+                        patterns::render(led, pattern, &cols, &palette, &params, map, &anims[0]);
+                        for (layer, anim) in layers.iter().zip(&anims[1..]) {
+                            let layer_params = resolve(layer.pattern, &layer.params);
+                            render_layer(led, layer, &layer_params, &palettes_data, config.main.brightness, map, anim);
+                        }
+                    }
+                    patterns::step(pattern, &params, &mut anims[0], map, &frame);
+                    for (layer, anim) in layers.iter().zip(&mut anims[1..]) {
+                        patterns::step(layer.pattern, &resolve(layer.pattern, &layer.params), anim, map, &frame);
+                    }
+                    if let Some((alert, level, _)) = alert {
+                        let layer = Layer::from_alert(&alert, level);
+                        let layer_params = resolve(layer.pattern, &layer.params);
+                        render_layer(led, &layer, &layer_params, &palettes_data, config.main.brightness, map, alert_anim);
+                        patterns::step(layer.pattern, &layer_params, alert_anim, map, &frame);
+                    }
+                    let buffer = led.encode();
+                    spi.iter_mut().for_each(|i| {
+                        let _ = i.write(buffer);
+                    });
                 }
             }
-            drop(leds_data);
-            offset = (offset + config.patterns.scroll_speed) % 1.0;
-            for i in &mut sprites {
-                i.run();
-                //println!("{:?}", &i);
-            }
-            
-            sleep(Duration::from_secs_f32(config.main.secs_per_update));
+
+            sleep(period);
         }
     });
 
@@ -349,15 +622,39 @@ async fn main() {
     let set = warp::path("set")
         .and(warp::post())
         .and(warp::path::param::<usize>())
-        .and(warp::body::content_length_limit(500))
+        .and(warp::body::content_length_limit(4096))
         .and(warp::body::json())
         .and(led_state.clone())
-        .map(|led_id: usize, post: LedState, leds_data: Arc<Mutex<Vec<LedState>>>| {
+        .and(patterns.clone())
+        .and(palettes.clone())
+        .and(scripts.clone())
+        .and(media.clone())
+        .map(|led_id: usize, post: LedState, leds_data: Arc<Mutex<Vec<LedState>>>, patterns: Arc<Vec<PatternInfo>>, palettes: Arc<Mutex<Palettes>>, scripts: Arc<Mutex<Scripts>>, media: Arc<Mutex<MediaLibrary>>| {
             let mut leds_data = match leds_data.lock() {
                 Ok(guard) => guard,
                 Err(poisoned) => poisoned.into_inner(),
             };
+            let palettes = match palettes.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let scripts = match scripts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let media = match media.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
             if leds_data.get(led_id).is_some() {
+                let pattern = post.pattern.or(leds_data[led_id].pattern).unwrap_or(0);
+                let mut valid = validate_pattern(&patterns, &palettes, &scripts, &media, pattern, post.params.as_ref());
+                for layer in post.layers.iter().flatten() {
+                    valid = valid.and_then(|_| validate_pattern(&patterns, &palettes, &scripts, &media, layer.pattern, Some(&layer.params)));
+                }
+                if let Err(msg) = valid {
+                    return warp::reply::json(&Error {msg: &msg});
+                }
                 if post.color1.is_some() {
                     leds_data[led_id].color1 = post.color1;
                 }
@@ -370,21 +667,311 @@ async fn main() {
                 if post.pattern.is_some() {
                     leds_data[led_id].pattern = post.pattern;
                 }
-                if let Ok(mut file) = File::create(format!("state.{}", led_id)) {
-                    let _ = file.write_all(&leds_data[led_id].serialize());
+                if post.layers.is_some() {
+                    leds_data[led_id].layers = post.layers;
                 }
+                if let Some(params) = &post.params {
+                    leds_data[led_id].params.get_or_insert_with(Params::new).merge(params);
+                }
+                if post.interpolation.is_some() {
+                    leds_data[led_id].interpolation = post.interpolation;
+                }
+                leds_data[led_id].save(led_id);
                 warp::reply::json(&Success {msg: "OK"})
             } else {
                 warp::reply::json(&Error {msg: "invalid ID"})
             }
         });
 
+    let alert = warp::path("alert")
+        .and(warp::post())
+        .and(warp::path::param::<usize>())
+        .and(warp::body::content_length_limit(500))
+        .and(warp::body::json())
+        .and(alerts.clone())
+        .and(patterns.clone())
+        .and(palettes.clone())
+        .and(scripts.clone())
+        .and(media.clone())
+        .map(|led_id: usize, post: Alert, alerts: Arc<Mutex<Vec<AlertQueue>>>, patterns: Arc<Vec<PatternInfo>>, palettes: Arc<Mutex<Palettes>>, scripts: Arc<Mutex<Scripts>>, media: Arc<Mutex<MediaLibrary>>| {
+            let mut alerts = match alerts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let palettes = match palettes.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let scripts = match scripts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let media = match media.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if let Err(msg) = post.validate() {
+                return warp::reply::json(&Error {msg});
+            }
+            if let Err(msg) = validate_pattern(&patterns, &palettes, &scripts, &media, post.pattern.unwrap_or(1), Some(&post.params)) {
+                return warp::reply::json(&Error {msg: &msg});
+            }
+            match alerts.get_mut(led_id) {
+                Some(queue) => match queue.push(post) {
+                    Ok(_) => warp::reply::json(&Success {msg: "OK"}),
+                    Err(msg) => warp::reply::json(&Error {msg}),
+                },
+                None => warp::reply::json(&Error {msg: "invalid ID"}),
+            }
+        });
+
+    // scrolls a message across a matrix, queued with the alerts so the device goes back to
+    // its state once the message has played
+    let text = warp::path("text")
+        .and(warp::post())
+        .and(warp::path::param::<usize>())
+        .and(warp::body::content_length_limit(2000))
+        .and(warp::body::json())
+        .and(alerts.clone())
+        .and(text_widths)
+        .map(|led_id: usize, post: TextMessage, alerts: Arc<Mutex<Vec<AlertQueue>>>, widths: Vec<Option<usize>>| {
+            let mut alerts = match alerts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let (queue, width) = match (alerts.get_mut(led_id), widths.get(led_id)) {
+                (Some(queue), Some(Some(width))) => (queue, *width),
+                (Some(_), Some(None)) => return warp::reply::json(&Error {msg: "device is not a matrix"}),
+                _ => return warp::reply::json(&Error {msg: "invalid ID"}),
+            };
+            match post.to_alert(width).and_then(|alert| queue.push(alert)) {
+                Ok(_) => warp::reply::json(&Success {msg: "OK"}),
+                Err(msg) => warp::reply::json(&Error {msg}),
+            }
+        });
+
+    // sets the `value` param without saving the state, so frequent readings don't wear out
+    // the storage
+    let meter = warp::path("meter")
+        .and(warp::post())
+        .and(warp::path::param::<usize>())
+        .and(warp::body::content_length_limit(200))
+        .and(warp::body::json())
+        .and(led_state.clone())
+        .map(|led_id: usize, post: MeterReading, leds_data: Arc<Mutex<Vec<LedState>>>| {
+            let mut leds_data = match leds_data.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let (min, max) = (post.min.unwrap_or(0.0), post.max.unwrap_or(1.0));
+            if !(post.value.is_finite() && min.is_finite() && max.is_finite() && max > min) {
+                return warp::reply::json(&Error {msg: "value must be a number and max greater than min"});
+            }
+            match leds_data.get_mut(led_id) {
+                Some(led) => {
+                    let level = ((post.value - min) / (max - min)).clamp(0.0, 1.0);
+                    led.params.get_or_insert_with(Params::new).merge(&Params::new().with("value", ParamValue::Number(level)));
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                None => warp::reply::json(&Error {msg: "invalid ID"}),
+            }
+        });
+
+    let alert_clear = warp::path("alert")
+        .and(warp::delete())
+        .and(warp::path::param::<usize>())
+        .and(alerts.clone())
+        .map(|led_id: usize, alerts: Arc<Mutex<Vec<AlertQueue>>>| {
+            let mut alerts = match alerts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match alerts.get_mut(led_id) {
+                Some(queue) => {
+                    queue.clear();
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                None => warp::reply::json(&Error {msg: "invalid ID"}),
+            }
+        });
+
     let patterns = warp::path("patterns")
         .and(patterns.clone())
-        .map(|v| {
-            warp::reply::json(&v)
+        .map(|v: Arc<Vec<PatternInfo>>| {
+            warp::reply::json(&*v)
         });
     
+    let palette_list = warp::path("palettes")
+        .and(warp::get())
+        .and(warp::path::end())
+        .and(palettes.clone())
+        .map(|palettes: Arc<Mutex<Palettes>>| {
+            let palettes = match palettes.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            warp::reply::json(palettes.list())
+        });
+
+    let palette_set = warp::path("palettes")
+        .and(warp::post())
+        .and(warp::path::param::<String>())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::json())
+        .and(palettes.clone())
+        .map(|name: String, post: Palette, palettes: Arc<Mutex<Palettes>>| {
+            let mut palettes = match palettes.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match palettes.set(&name, post) {
+                Ok(_) => {
+                    palettes.save();
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                Err(msg) => warp::reply::json(&Error {msg}),
+            }
+        });
+
+    let palette_delete = warp::path("palettes")
+        .and(warp::delete())
+        .and(warp::path::param::<String>())
+        .and(palettes.clone())
+        .map(|name: String, palettes: Arc<Mutex<Palettes>>| {
+            let mut palettes = match palettes.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match palettes.remove(&name) {
+                Ok(_) => {
+                    palettes.save();
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                Err(msg) => warp::reply::json(&Error {msg}),
+            }
+        });
+
+    let script_list = warp::path("scripts")
+        .and(warp::get())
+        .and(warp::path::end())
+        .and(scripts.clone())
+        .map(|scripts: Arc<Mutex<Scripts>>| {
+            let scripts = match scripts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            warp::reply::json(&scripts.list())
+        });
+
+    let script_get = warp::path("scripts")
+        .and(warp::get())
+        .and(warp::path::param::<String>())
+        .and(scripts.clone())
+        .map(|name: String, scripts: Arc<Mutex<Scripts>>| {
+            let scripts = match scripts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match scripts.get_source(&name) {
+                Some(source) => warp::reply::json(&Success {msg: source}),
+                None => warp::reply::json(&Error {msg: "unknown script"}),
+            }
+        });
+
+    // the body is the script source, a compile error is sent back in the reply
+    let script_set = warp::path("scripts")
+        .and(warp::post())
+        .and(warp::path::param::<String>())
+        .and(warp::body::content_length_limit(scripts::MAX_SOURCE as u64))
+        .and(warp::body::bytes())
+        .and(scripts.clone())
+        .map(|name: String, body: warp::hyper::body::Bytes, scripts: Arc<Mutex<Scripts>>| {
+            let mut scripts = match scripts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let source = match std::str::from_utf8(&body) {
+                Ok(source) => source,
+                Err(_) => return warp::reply::json(&Error {msg: "script must be UTF-8 text"}),
+            };
+            match scripts.set(&name, source) {
+                Ok(_) => {
+                    scripts.save(&name);
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                Err(msg) => warp::reply::json(&Error {msg: &msg}),
+            }
+        });
+
+    let script_delete = warp::path("scripts")
+        .and(warp::delete())
+        .and(warp::path::param::<String>())
+        .and(scripts.clone())
+        .map(|name: String, scripts: Arc<Mutex<Scripts>>| {
+            let mut scripts = match scripts.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match scripts.remove(&name) {
+                Ok(_) => {
+                    scripts.save(&name);
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                Err(msg) => warp::reply::json(&Error {msg}),
+            }
+        });
+
+    let media_list = warp::path("media")
+        .and(warp::get())
+        .and(warp::path::end())
+        .and(media.clone())
+        .map(|media: Arc<Mutex<MediaLibrary>>| {
+            let media = match media.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            warp::reply::json(&media.list())
+        });
+
+    // the body is the PNG or GIF file itself
+    let media_set = warp::path("media")
+        .and(warp::post())
+        .and(warp::path::param::<String>())
+        .and(warp::body::content_length_limit(media::MAX_UPLOAD as u64))
+        .and(warp::body::bytes())
+        .and(media.clone())
+        .map(|name: String, body: warp::hyper::body::Bytes, media: Arc<Mutex<MediaLibrary>>| {
+            let mut media = match media.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match media.set(&name, &body) {
+                Ok(_) => {
+                    media.save(&name);
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                Err(msg) => warp::reply::json(&Error {msg: &msg}),
+            }
+        });
+
+    let media_delete = warp::path("media")
+        .and(warp::delete())
+        .and(warp::path::param::<String>())
+        .and(media.clone())
+        .map(|name: String, media: Arc<Mutex<MediaLibrary>>| {
+            let mut media = match media.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match media.remove(&name) {
+                Ok(_) => {
+                    media.save(&name);
+                    warp::reply::json(&Success {msg: "OK"})
+                }
+                Err(msg) => warp::reply::json(&Error {msg}),
+            }
+        });
+
     let devices = warp::path("devices")
         .and(devices.clone())
         .map(|v| {
@@ -396,7 +983,21 @@ async fn main() {
         .or(pkg)
         .or(get)
         .or(set)
+        .or(alert)
+        .or(alert_clear)
+        .or(text)
+        .or(meter)
         .or(patterns)
+        .or(palette_list)
+        .or(palette_set)
+        .or(palette_delete)
+        .or(script_list)
+        .or(script_get)
+        .or(script_set)
+        .or(script_delete)
+        .or(media_list)
+        .or(media_set)
+        .or(media_delete)
         .or(devices);
 
     warp::serve(routes)
@@ -0,0 +1,290 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub z: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(&self, other: Point) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn distance(&self, other: Point) -> f32 {
+        let dx = self.x - other.x;
+        let dy = self.y - other.y;
+        let dz = self.z - other.z;
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+
+    pub fn normalized(&self) -> Self {
+        let len = self.dot(*self).sqrt();
+        if len > 0.0 {
+            Self::new(self.x / len, self.y / len, self.z / len)
+        } else {
+            Self::new(1.0, 0.0, 0.0)
+        }
+    }
+}
+
+// entries of a JSON map file, either `[x, y, z]` arrays or `{"x": .., "y": .., "z": ..}` objects
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonPoint {
+    Array(Vec<f32>),
+    Object(Point),
+}
+
+/// Rows and columns of an LED matrix, wired row by row from the top left. With `serpentine`
+/// wiring every other row runs right to left.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct Matrix {
+    pub width: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub serpentine: bool,
+}
+
+impl Matrix {
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// LED number of column `x` in row `y`, counting rows from the top.
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        if self.serpentine && y % 2 == 1 {
+            y * self.width + (self.width - 1 - x)
+        } else {
+            y * self.width + x
+        }
+    }
+}
+
+/// Physical (x, y, z) coordinate of every LED on a device, scaled so the longest side of the
+/// bounding box spans 0.0 to 1.0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelMap {
+    points: Vec<Point>,
+    // row and column layout, for patterns that work on a grid
+    matrix: Option<Matrix>,
+}
+
+impl PixelMap {
+    /// A straight line along the x axis, used for devices without a map file.
+    pub fn linear(len: usize) -> Self {
+        let points = (0..len)
+            .map(|i| {
+                let x = if len > 1 { (i as f32) / ((len - 1) as f32) } else { 0.0 };
+                Point::new(x, 0.0, 0.0)
+            })
+            .collect();
+        Self { points, matrix: None }
+    }
+
+    /// A flat grid for a matrix without a map file, with the first row at the top.
+    pub fn grid(matrix: Matrix) -> Self {
+        let mut points = vec![Point::new(0.0, 0.0, 0.0); matrix.len()];
+        for y in 0..matrix.height {
+            for x in 0..matrix.width {
+                points[matrix.index(x, y)] = Point::new(x as f32, (matrix.height - 1 - y) as f32, 0.0);
+            }
+        }
+        let mut map = Self { points, matrix: Some(matrix) };
+        map.normalize();
+        map
+    }
+
+    pub fn with_matrix(mut self, matrix: Matrix) -> Self {
+        self.matrix = Some(matrix);
+        self
+    }
+
+    /// Loads a map from a `.json` file, or from CSV for any other extension.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let raw = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let is_json = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let map = if is_json { Self::from_json(&raw) } else { Self::from_csv(&raw) };
+        map.map_err(|err| format!("{}: {}", path, err))
+    }
+
+    /// One LED per line as `x,y,z` (or `x,y` for flat layouts). Blank lines, `#` comments and
+    /// a non-numeric header line are skipped.
+    pub fn from_csv(raw: &str) -> Result<Self, String> {
+        let mut points = Vec::new();
+        for (line_num, line) in raw.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Result<Vec<f32>, _> = line.split(',').map(|v| v.trim().parse::<f32>()).collect();
+            match values {
+                Ok(v) => points.push(point_from_slice(&v).map_err(|err| format!("line {}: {}", line_num + 1, err))?),
+                Err(_) if points.is_empty() && line_num == 0 => continue,
+                Err(err) => return Err(format!("line {}: {}", line_num + 1, err)),
+            }
+        }
+        Self::from_points(points)
+    }
+
+    /// A JSON array with one entry per LED, either `[x, y, z]` or `{"x": .., "y": .., "z": ..}`.
+    pub fn from_json(raw: &str) -> Result<Self, String> {
+        let entries: Vec<JsonPoint> = serde_json::from_str(raw).map_err(|err| err.to_string())?;
+        let mut points = Vec::with_capacity(entries.len());
+        for (index, entry) in entries.into_iter().enumerate() {
+            let point = match entry {
+                JsonPoint::Array(v) => point_from_slice(&v).map_err(|err| format!("entry {}: {}", index, err))?,
+                JsonPoint::Object(p) => p,
+            };
+            points.push(point);
+        }
+        Self::from_points(points)
+    }
+
+    fn from_points(points: Vec<Point>) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err(String::from("map contains no points"));
+        }
+        let mut map = Self { points, matrix: None };
+        map.normalize();
+        Ok(map)
+    }
+
+    fn bounds(&self) -> (Point, Point) {
+        let mut min = Point::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point::new(f32::MIN, f32::MIN, f32::MIN);
+        for p in &self.points {
+            min = Point::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        (min, max)
+    }
+
+    // moves the map to the origin and scales it uniformly, so shapes keep their proportions
+    fn normalize(&mut self) {
+        let (min, max) = self.bounds();
+        let size = (max.x - min.x).max(max.y - min.y).max(max.z - min.z);
+        let scale = if size > 0.0 { 1.0 / size } else { 1.0 };
+        for p in &mut self.points {
+            *p = Point::new((p.x - min.x) * scale, (p.y - min.y) * scale, (p.z - min.z) * scale);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn get_points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn get_matrix(&self) -> Option<&Matrix> {
+        self.matrix.as_ref()
+    }
+
+    /// Center of the bounding box.
+    pub fn center(&self) -> Point {
+        let (min, max) = self.bounds();
+        Point::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, (min.z + max.z) / 2.0)
+    }
+
+    /// Distance from the center to the furthest LED.
+    pub fn radius(&self) -> f32 {
+        let center = self.center();
+        self.points.iter().map(|p| p.distance(center)).fold(0.0, f32::max)
+    }
+
+    /// Where each LED sits around the center in the x-y plane, as a fraction of a turn clockwise
+    /// from straight up and a distance in LED spacings, taking the spacing as the median gap
+    /// between neighbours in wiring order. `None` for maps with no height or width, like a plain
+    /// strip.
+    pub fn polar(&self) -> Option<Vec<(f32, f32)>> {
+        let (min, max) = self.bounds();
+        if max.x - min.x <= 0.0 || max.y - min.y <= 0.0 {
+            return None;
+        }
+        let mut gaps: Vec<f32> = self.points.windows(2).map(|w| w[0].distance(w[1])).filter(|d| *d > 0.0).collect();
+        gaps.sort_by(f32::total_cmp);
+        let spacing = gaps.get(gaps.len() / 2).copied().unwrap_or(1.0);
+        let center = self.center();
+        let polar = self.points.iter().map(|p| {
+            let (dx, dy) = (p.x - center.x, p.y - center.y);
+            let turn = (dx.atan2(dy) / std::f32::consts::TAU).rem_euclid(1.0);
+            (turn, dx.hypot(dy) / spacing)
+        });
+        Some(polar.collect())
+    }
+
+    /// Smallest and largest projection of the LEDs onto `direction`.
+    pub fn extent_along(&self, direction: Point) -> (f32, f32) {
+        self.points.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+            let d = p.dot(direction);
+            (min.min(d), max.max(d))
+        })
+    }
+}
+
+fn point_from_slice(values: &[f32]) -> Result<Point, String> {
+    match values {
+        [x, y] => Ok(Point::new(*x, *y, 0.0)),
+        [x, y, z] => Ok(Point::new(*x, *y, *z)),
+        _ => Err(format!("expected 2 or 3 coordinates, found {}", values.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps() {
+        let csv = "x,y,z\n0,0,0\n# comment\n\n2,1,0\n4,2,1\n";
+        let map = PixelMap::from_csv(csv).unwrap();
+        assert_eq!(map.len(), 3);
+        // -test- the longest side is scaled to 1.0 and proportions are kept
+        assert_eq!(map.get_points()[1], Point::new(0.5, 0.25, 0.0));
+        assert_eq!(map.get_points()[2], Point::new(1.0, 0.5, 0.25));
+
+        let json = r#"[[0, 0, 0], {"x": 2, "y": 1}, [4, 2, 1]]"#;
+        assert_eq!(PixelMap::from_json(json).unwrap(), map);
+
+        assert!(PixelMap::from_csv("1,2,3\n4,5\n6\n").is_err());
+        assert!(PixelMap::from_csv("1,2,3\nfoo,bar,baz\n").is_err());
+        assert!(PixelMap::from_json("[]").is_err());
+    }
+
+    #[test]
+    fn test_map_geometry() {
+        let map = PixelMap::linear(5);
+        assert_eq!(map.get_points()[4], Point::new(1.0, 0.0, 0.0));
+        assert_eq!(map.center(), Point::new(0.5, 0.0, 0.0));
+        assert_eq!(map.radius(), 0.5);
+        assert_eq!(map.extent_along(Point::new(-1.0, 0.0, 0.0)), (-1.0, 0.0));
+
+        // -test- serpentine rows run back and forth, the first row is at the top
+        let matrix = Matrix { width: 3, height: 2, serpentine: true };
+        assert_eq!(matrix.index(0, 1), 5);
+        let grid = PixelMap::grid(matrix);
+        assert_eq!(grid.get_points()[0], Point::new(0.0, 0.5, 0.0));
+        assert_eq!(grid.get_points()[5], Point::new(0.0, 0.0, 0.0));
+        assert_eq!(grid.get_matrix(), Some(&matrix));
+
+        // -test- LEDs of a grid sit around its center in turns clockwise from up, strips don't
+        assert_eq!(map.polar(), None);
+        let polar = PixelMap::grid(Matrix { width: 3, height: 3, serpentine: false }).polar().unwrap();
+        assert_eq!(polar[1], (0.0, 1.0));
+        assert_eq!(polar[5], (0.25, 1.0));
+        assert_eq!(polar[4].1, 0.0);
+    }
+}
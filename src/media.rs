@@ -0,0 +1,441 @@
+// images and animated GIFs for the image pattern. A PNG is played a row at a time along a
+// strip, for light painting with a moving strip, and shown whole on a matrix. A GIF plays its
+// frames, on a matrix scaled to fit and on a strip along its middle row.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::num::NonZeroU64;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::colors::ColorRgb;
+use crate::maps::PixelMap;
+use crate::params::Params;
+
+pub const MAX_UPLOAD: usize = 1024 * 1024;
+pub const MAX_MEDIA: usize = 32;
+// largest width or height, and most pixels over all frames, so a small file can't unpack into
+// more memory than a Pi has to spare
+const MAX_SIZE: u32 = 1024;
+const MAX_PIXELS: usize = 1 << 20;
+// rows per second of a PNG at a speed of 1, one per frame at the usual 50 Hz
+const ROW_RATE: f32 = 50.0;
+// GIF frames with no delay set are shown this long, as browsers do
+const MIN_DELAY: f32 = 0.02;
+
+const DIR: &str = "media";
+
+#[derive(Debug, Copy, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Png,
+    Gif,
+}
+
+impl MediaKind {
+    fn extension(self) -> &'static str {
+        match self {
+            MediaKind::Png => "png",
+            MediaKind::Gif => "gif",
+        }
+    }
+}
+
+/// A decoded image or animation.
+#[derive(Debug, Clone)]
+pub struct Media {
+    kind: MediaKind,
+    width: usize,
+    height: usize,
+    // each frame row by row from the top left, a PNG has just the one
+    frames: Vec<Vec<ColorRgb>>,
+    // seconds each GIF frame is shown
+    delays: Vec<f32>,
+    // the file as it was uploaded, for saving
+    file: Vec<u8>,
+    // set by the library on each upload, so a frame cached from media that has since been
+    // replaced isn't reused
+    revision: u64,
+}
+
+/// A piece of media as listed by `/media`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct MediaStatus<'a> {
+    pub name: &'a str,
+    pub kind: MediaKind,
+    pub width: usize,
+    pub height: usize,
+    pub frames: usize,
+}
+
+impl Media {
+    /// Decodes a PNG or GIF, told apart by their signatures.
+    pub fn decode(file: &[u8]) -> Result<Self, String> {
+        if file.starts_with(b"\x89PNG") {
+            decode_png(file)
+        } else if file.starts_with(b"GIF8") {
+            decode_gif(file)
+        } else {
+            Err(String::from("media must be a PNG or GIF"))
+        }
+    }
+
+    // rows of a PNG, or frames of a GIF
+    fn steps(&self) -> usize {
+        match self.kind {
+            MediaKind::Png => self.height,
+            MediaKind::Gif => self.frames.len(),
+        }
+    }
+
+    fn delay(&self, step: usize) -> f32 {
+        match self.kind {
+            MediaKind::Png => 1.0 / ROW_RATE,
+            MediaKind::Gif => self.delays[step],
+        }
+    }
+
+    /// The row or frame showing `time` seconds in, or `None` once `loops` plays are done.
+    /// Zero loops plays forever.
+    pub fn step_at(&self, time: f32, loops: usize) -> Option<usize> {
+        let total: f32 = (0..self.steps()).map(|s| self.delay(s)).sum();
+        if total <= 0.0 || (loops > 0 && time >= total * loops as f32) {
+            return None;
+        }
+        let mut left = time.max(0.0) % total;
+        for step in 0..self.steps() {
+            left -= self.delay(step);
+            if left < 0.0 {
+                return Some(step);
+            }
+        }
+        Some(self.steps() - 1)
+    }
+
+    /// Colors for the LEDs laid out by `map` at row or frame `step`, resampled to fit.
+    pub fn render(&self, step: usize, map: &PixelMap) -> Vec<ColorRgb> {
+        let (frame, fixed_v) = match self.kind {
+            MediaKind::Png => (&self.frames[0], step as f32 / (self.height.max(2) - 1) as f32),
+            MediaKind::Gif => (&self.frames[step.min(self.frames.len() - 1)], 0.5),
+        };
+        let mut pixels = vec![ColorRgb::new(0, 0, 0); map.len()];
+        match map.get_matrix() {
+            Some(matrix) => {
+                for y in 0..matrix.height {
+                    for x in 0..matrix.width {
+                        let (u, v) = (fraction(x, matrix.width), fraction(y, matrix.height));
+                        if let Some(pixel) = pixels.get_mut(matrix.index(x, y)) {
+                            *pixel = self.sample(frame, u, v);
+                        }
+                    }
+                }
+            }
+            None => {
+                let len = pixels.len();
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = self.sample(frame, fraction(i, len), fixed_v);
+                }
+            }
+        }
+        pixels
+    }
+
+    // the color at `u` across and `v` down, 0.0 to 1.0, blended from the nearest four pixels
+    fn sample(&self, frame: &[ColorRgb], u: f32, v: f32) -> ColorRgb {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let y = v.clamp(0.0, 1.0) * (self.height - 1) as f32;
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: usize, y: usize| frame[y * self.width + x];
+        let mix = |a: u8, b: u8, t: f32| a as f32 + (b as f32 - a as f32) * t;
+        let channel = |get: fn(&ColorRgb) -> u8| {
+            let top = mix(get(&at(x0, y0)), get(&at(x1, y0)), tx);
+            let bottom = mix(get(&at(x0, y1)), get(&at(x1, y1)), tx);
+            (top + (bottom - top) * ty).round() as u8
+        };
+        ColorRgb::new(channel(ColorRgb::get_r), channel(ColorRgb::get_g), channel(ColorRgb::get_b))
+    }
+}
+
+/// The last row or frame of media sampled onto a device's map, kept so it is only sampled
+/// again when the step, the map or the media changes.
+#[derive(Debug, Clone, Default)]
+pub struct MediaFrame {
+    // revision of the media and the step the pixels are from
+    key: Option<(u64, usize)>,
+    map: Option<PixelMap>,
+    pixels: Vec<ColorRgb>,
+}
+
+impl MediaFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `media` at `step` on the LEDs laid out by `map`, as `Media::render` gives it.
+    pub fn render(&mut self, media: &Media, step: usize, map: &PixelMap) -> &[ColorRgb] {
+        let key = Some((media.revision, step));
+        let same_map = self.map.as_ref() == Some(map);
+        if self.key != key || !same_map {
+            self.pixels = media.render(step, map);
+            self.key = key;
+            if !same_map {
+                self.map = Some(map.clone());
+            }
+        }
+        &self.pixels
+    }
+}
+
+// position of `i` of `len` from 0.0 to 1.0
+fn fraction(i: usize, len: usize) -> f32 {
+    if len > 1 { i as f32 / (len - 1) as f32 } else { 0.5 }
+}
+
+fn check_size(width: usize, height: usize, frames: usize) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(String::from("image is empty"));
+    }
+    if width > MAX_SIZE as usize || height > MAX_SIZE as usize || width * height * frames > MAX_PIXELS {
+        return Err(format!("media must be at most {} pixels across and {} pixels over all frames", MAX_SIZE, MAX_PIXELS));
+    }
+    Ok(())
+}
+
+fn decode_png(file: &[u8]) -> Result<Media, String> {
+    let mut decoder = png::Decoder::new_with_limits(file, png::Limits { bytes: MAX_PIXELS * 8 });
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let (width, height) = (reader.info().width as usize, reader.info().height as usize);
+    check_size(width, height, 1)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|err| err.to_string())?;
+    let channels = info.color_type.samples();
+    let pixels = buf[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|p| match p {
+            [l] => ColorRgb::new(*l, *l, *l),
+            [l, a] => over_black(*l, *l, *l, *a),
+            [r, g, b] => ColorRgb::new(*r, *g, *b),
+            [r, g, b, a, ..] => over_black(*r, *g, *b, *a),
+            _ => ColorRgb::new(0, 0, 0),
+        })
+        .collect();
+    Ok(Media { kind: MediaKind::Png, width, height, frames: vec![pixels], delays: vec![0.0], file: file.to_vec(), revision: 0 })
+}
+
+fn decode_gif(file: &[u8]) -> Result<Media, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    options.set_memory_limit(gif::MemoryLimit::Bytes(NonZeroU64::new(MAX_PIXELS as u64 * 4).expect("limit is above 0")));
+    let mut decoder = options.read_info(file).map_err(|err| err.to_string())?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+    check_size(width, height, 1)?;
+    // frames only cover part of the image, so each is drawn over what the last one left
+    let mut canvas = vec![[0u8; 4]; width * height];
+    let (mut frames, mut delays) = (Vec::new(), Vec::new());
+    while let Some(frame) = decoder.read_next_frame().map_err(|err| err.to_string())? {
+        check_size(width, height, frames.len() + 1)?;
+        let before = canvas.clone();
+        let (left, top, w, h) = (frame.left as usize, frame.top as usize, frame.width as usize, frame.height as usize);
+        for (i, rgba) in frame.buffer.chunks_exact(4).enumerate() {
+            let (x, y) = (left + i % w.max(1), top + i / w.max(1));
+            if x < width && y < height && rgba[3] > 0 {
+                canvas[y * width + x] = [rgba[0], rgba[1], rgba[2], rgba[3]];
+            }
+        }
+        frames.push(canvas.iter().map(|p| over_black(p[0], p[1], p[2], p[3])).collect());
+        delays.push((frame.delay as f32 / 100.0).max(MIN_DELAY));
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + h).min(height) {
+                    for x in left..(left + w).min(width) {
+                        canvas[y * width + x] = [0; 4];
+                    }
+                }
+            }
+            gif::DisposalMethod::Previous => canvas = before,
+            _ => (),
+        }
+    }
+    if frames.is_empty() {
+        return Err(String::from("GIF has no frames"));
+    }
+    Ok(Media { kind: MediaKind::Gif, width, height, frames, delays, file: file.to_vec(), revision: 0 })
+}
+
+fn over_black(r: u8, g: u8, b: u8, a: u8) -> ColorRgb {
+    let scale = |c: u8| (c as u16 * a as u16 / 255) as u8;
+    ColorRgb::new(scale(r), scale(g), scale(b))
+}
+
+/// Every uploaded image and animation by name.
+#[derive(Debug, Clone, Default)]
+pub struct MediaLibrary {
+    media: BTreeMap<String, Media>,
+    // uploads so far, numbering each one's revision
+    revisions: u64,
+}
+
+impl MediaLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Media> {
+        self.media.get(name)
+    }
+
+    pub fn list(&self) -> Vec<MediaStatus<'_>> {
+        self.media
+            .iter()
+            .map(|(name, m)| MediaStatus { name, kind: m.kind, width: m.width, height: m.height, frames: m.frames.len() })
+            .collect()
+    }
+
+    /// Checks that the `media` param, if set, names existing media.
+    pub fn check(&self, params: &Params) -> Result<(), String> {
+        match params.get("media") {
+            Some(_) if self.get(params.get_str("media", "")).is_none() => Err(String::from("unknown media")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes `file` and stores it as `name`, replacing any media of that name.
+    pub fn set(&mut self, name: &str, file: &[u8]) -> Result<(), String> {
+        if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(String::from("media names are 1 to 32 letters, digits, '_' or '-'"));
+        }
+        if file.len() > MAX_UPLOAD {
+            return Err(String::from("file is too large"));
+        }
+        if !self.media.contains_key(name) && self.media.len() >= MAX_MEDIA {
+            return Err(String::from("too much media"));
+        }
+        let mut media = Media::decode(file)?;
+        self.revisions += 1;
+        media.revision = self.revisions;
+        self.media.insert(String::from(name), media);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), &'static str> {
+        match self.media.remove(name) {
+            Some(_) => Ok(()),
+            None => Err("unknown media"),
+        }
+    }
+
+    /// Adds every PNG and GIF in the media directory, reporting those that don't decode.
+    pub fn load(&mut self) {
+        let entries = match fs::read_dir(DIR) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension().is_some_and(|e| e == "png" || e == "gif") {
+                let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+                let result = fs::read(&path).map_err(|err| err.to_string()).and_then(|file| self.set(&name, &file));
+                if let Err(err) = result {
+                    println!("error loading media {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    /// Writes the media `name` to the media directory, or deletes its file if it has been
+    /// removed.
+    pub fn save(&self, name: &str) {
+        for kind in [MediaKind::Png, MediaKind::Gif] {
+            let _ = fs::remove_file(Path::new(DIR).join(format!("{}.{}", name, kind.extension())));
+        }
+        if let Some(media) = self.media.get(name) {
+            let _ = fs::create_dir_all(DIR);
+            let _ = fs::write(Path::new(DIR).join(format!("{}.{}", name, media.kind.extension())), &media.file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::maps::Matrix;
+
+    fn png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.write_header().unwrap().write_image_data(rgb).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_png() {
+        // -test- a red row then a blue row, played a row at a time along a strip
+        let image = Media::decode(&png(2, 2, &[255, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 255])).unwrap();
+        let strip = PixelMap::linear(3);
+        assert_eq!(image.step_at(0.0, 0), Some(0));
+        assert_eq!(image.step_at(1.5 / ROW_RATE, 0), Some(1));
+        assert_eq!(image.render(0, &strip), [ColorRgb::new(255, 0, 0); 3]);
+        assert_eq!(image.render(1, &strip), [ColorRgb::new(0, 0, 255); 3]);
+
+        // -test- playback stops after the set number of loops
+        assert_eq!(image.step_at(2.5 / ROW_RATE, 0), Some(0));
+        assert_eq!(image.step_at(2.5 / ROW_RATE, 1), None);
+
+        // -test- on a matrix the image is scaled to fit
+        let grid = PixelMap::grid(Matrix { width: 3, height: 3, serpentine: false });
+        let pixels = image.render(0, &grid);
+        assert_eq!(pixels[0], ColorRgb::new(255, 0, 0));
+        assert_eq!(pixels[4], ColorRgb::new(128, 0, 128));
+        assert_eq!(pixels[8], ColorRgb::new(0, 0, 255));
+
+        // -test- anything else, or too large, is rejected
+        let mut library = MediaLibrary::new();
+        assert!(library.set("text", b"hello").is_err());
+        assert!(library.set("wide", &png(2000, 1, &[0; 6000])).is_err());
+        assert!(library.set("rows", &png(2, 2, &[0; 12])).is_ok());
+        assert_eq!(library.list()[0].kind, MediaKind::Png);
+    }
+
+    #[test]
+    fn test_media_frame() {
+        let mut library = MediaLibrary::new();
+        library.set("rows", &png(2, 2, &[255, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 255])).unwrap();
+        let strip = PixelMap::linear(3);
+        let mut frame = MediaFrame::new();
+
+        // -test- the sampled step is reused until the step changes
+        assert_eq!(frame.render(library.get("rows").unwrap(), 0, &strip), [ColorRgb::new(255, 0, 0); 3]);
+        frame.pixels[0] = ColorRgb::new(1, 2, 3);
+        assert_eq!(frame.render(library.get("rows").unwrap(), 0, &strip)[0], ColorRgb::new(1, 2, 3));
+        assert_eq!(frame.render(library.get("rows").unwrap(), 1, &strip), [ColorRgb::new(0, 0, 255); 3]);
+
+        // -test- a different map or replaced media is sampled again
+        assert_eq!(frame.render(library.get("rows").unwrap(), 1, &PixelMap::linear(2)).len(), 2);
+        library.set("rows", &png(2, 2, &[0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0])).unwrap();
+        assert_eq!(frame.render(library.get("rows").unwrap(), 1, &PixelMap::linear(2)), [ColorRgb::new(0, 255, 0); 2]);
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut file = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut file, 2, 1, &[]).unwrap();
+            for (rgb, delay) in [([255, 0, 0, 255, 0, 0], 10), ([0, 255, 0, 0, 255, 0], 20)] {
+                let mut frame = gif::Frame::from_rgb(2, 1, &rgb);
+                frame.delay = delay;
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        // -test- frames play for their delays
+        let animation = Media::decode(&file).unwrap();
+        assert_eq!(animation.step_at(0.05, 0), Some(0));
+        assert_eq!(animation.step_at(0.15, 0), Some(1));
+        assert_eq!(animation.step_at(0.35, 0), Some(0));
+        assert_eq!(animation.render(1, &PixelMap::linear(2)), [ColorRgb::new(0, 255, 0); 2]);
+    }
+}
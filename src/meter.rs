@@ -0,0 +1,73 @@
+// how fast the peak marker falls back to the level once it has been held, in strip lengths per
+// second
+const PEAK_FALL: f32 = 0.5;
+
+/// A level meter easing toward the value it is given, with a peak marker that holds the
+/// highest recent level for a while before falling back.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Meter {
+    level: f32,
+    peak: f32,
+    // seconds the peak has been held
+    held: f32,
+}
+
+impl Meter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn get_peak(&self) -> f32 {
+        self.peak
+    }
+
+    /// Moves the level `dt` seconds toward `target`, covering about two thirds of the way every
+    /// `smoothing` seconds. The peak falls once it has been held for `hold` seconds.
+    pub fn step(&mut self, target: f32, smoothing: f32, hold: f32, dt: f32) {
+        let rate = if smoothing > 0.0 { 1.0 - (-dt / smoothing).exp() } else { 1.0 };
+        self.level += (target.clamp(0.0, 1.0) - self.level) * rate;
+        if self.level >= self.peak {
+            self.peak = self.level;
+            self.held = 0.0;
+        } else {
+            self.held += dt;
+            if self.held > hold {
+                self.peak = (self.peak - PEAK_FALL * dt).max(self.level);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter() {
+        // -test- without smoothing the level jumps straight to the target
+        let mut meter = Meter::new();
+        meter.step(0.8, 0.0, 1.0, 0.02);
+        assert_eq!(meter.get_level(), 0.8);
+
+        // -test- the peak holds, then falls back to the level
+        meter.step(0.2, 0.0, 1.0, 0.5);
+        assert_eq!(meter.get_peak(), 0.8);
+        meter.step(0.2, 0.0, 1.0, 0.6);
+        assert!(meter.get_peak() < 0.8);
+        for _ in 0..10 {
+            meter.step(0.2, 0.0, 1.0, 0.2);
+        }
+        assert_eq!(meter.get_peak(), 0.2);
+
+        // -test- smoothing eases toward the target
+        let mut meter = Meter::new();
+        meter.step(1.0, 0.5, 1.0, 0.5);
+        assert!((meter.get_level() - 0.632).abs() < 0.01);
+        meter.step(5.0, 0.5, 1.0, 10.0);
+        assert!((meter.get_level() - 1.0).abs() < 0.001);
+    }
+}
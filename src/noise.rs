@@ -0,0 +1,132 @@
+// gradient noise after Ken Perlin's "Improving Noise" (2002), with the permutation table
+// replaced by an integer hash so no table has to be stored
+
+use crate::maps::Point;
+
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// dot product of the offset with one of 12 gradients pointing at the edges of a cube
+fn grad(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Smooth 3D noise in roughly -1.0..1.0, zero at every integer lattice point.
+pub fn perlin3(x: f32, y: f32, z: f32) -> f32 {
+    let (xf, yf, zf) = (x.floor(), y.floor(), z.floor());
+    let (xi, yi, zi) = (xf as i32, yf as i32, zf as i32);
+    let (x, y, z) = (x - xf, y - yf, z - zf);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        grad(hash(xi + dx, yi + dy, zi + dz), x - dx as f32, y - dy as f32, z - dz as f32)
+    };
+
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+/// Fractal noise: `octaves` layers of `perlin3`, each at twice the frequency and half the
+/// amplitude of the one before, scaled back to roughly -1.0..1.0.
+pub fn fbm3(x: f32, y: f32, z: f32, octaves: usize) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut norm = 0.0;
+    let mut freq = 1.0;
+    for octave in 0..octaves.max(1) {
+        // shift each octave so their zeros at the lattice points don't line up
+        let shift = octave as f32 * 17.31;
+        total += perlin3(x * freq + shift, y * freq + shift, z * freq + shift) * amplitude;
+        norm += amplitude;
+        amplitude *= 0.5;
+        freq *= 2.0;
+    }
+    total / norm
+}
+
+/// A noise field sampled at pixel positions, drifting along z over time.
+#[derive(Debug, Copy, Clone)]
+pub struct NoiseField {
+    pub scale: f32,
+    pub time: f32,
+    pub octaves: usize,
+}
+
+impl NoiseField {
+    pub fn at(&self, point: Point) -> f32 {
+        fbm3(point.x * self.scale, point.y * self.scale, point.z * self.scale + self.time, self.octaves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perlin3() {
+        // -test- lattice points are always zero
+        assert_eq!(perlin3(0.0, 0.0, 0.0), 0.0);
+        assert_eq!(perlin3(3.0, -2.0, 7.0), 0.0);
+
+        // -test- output is deterministic, bounded and continuous
+        let mut prev = perlin3(0.0, 0.5, 0.5);
+        for i in 1..1000 {
+            let x = i as f32 * 0.01;
+            let value = perlin3(x, 0.5, 0.5);
+            assert_eq!(value, perlin3(x, 0.5, 0.5));
+            assert!((-1.5..=1.5).contains(&value));
+            assert!((value - prev).abs() < 0.1);
+            prev = value;
+        }
+    }
+
+    #[test]
+    fn test_fbm3() {
+        // -test- one octave is plain perlin noise
+        assert_eq!(fbm3(0.3, 0.7, 1.1, 1), perlin3(0.3, 0.7, 1.1));
+
+        // -test- more octaves add detail but stay in range
+        let mut differs = false;
+        for i in 0..500 {
+            let x = i as f32 * 0.037;
+            let value = fbm3(x, 0.5, 0.25, 4);
+            assert!((-1.5..=1.5).contains(&value));
+            differs |= value != fbm3(x, 0.5, 0.25, 1);
+        }
+        assert!(differs);
+    }
+}
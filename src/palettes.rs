@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use serde::{Serialize, Deserialize};
+
+use crate::colors::{ColorHsv, Interpolation};
+use crate::params::Params;
+
+// upper bound on stops per palette and on user palettes, so a client can't grow them forever
+pub const MAX_STOPS: usize = 32;
+pub const MAX_USER: usize = 64;
+
+const USER_FILE: &str = "palettes.json";
+
+/// Colors spread evenly around a loop, the last stop blending back into the first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Palette {
+    stops: Vec<ColorHsv>,
+}
+
+impl Palette {
+    pub fn new(stops: Vec<ColorHsv>) -> Self {
+        Self { stops }
+    }
+
+    fn from_hsv(stops: &[(f32, f32, f32)]) -> Self {
+        Self::new(stops.iter().map(|(h, s, v)| ColorHsv::new(*h, *s, *v)).collect())
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.stops.len() < 2 || self.stops.len() > MAX_STOPS {
+            return Err("palette must have between 2 and 32 stops");
+        }
+        Ok(())
+    }
+
+    /// The color at `pos`, wrapping around every 1.0, blended between stops in `interpolation`.
+    /// Hue takes the short way round in HSV too, so a palette can cross from magenta back to red.
+    pub fn at(&self, pos: f32, interpolation: Interpolation) -> ColorHsv {
+        let n = self.stops.len();
+        if n < 2 {
+            return self.stops.first().copied().unwrap_or_default();
+        }
+        let scaled = pos.rem_euclid(1.0) * n as f32;
+        let i = (scaled as usize).min(n - 1);
+        let (a, b) = (&self.stops[i], &self.stops[(i + 1) % n]);
+        let t = scaled - i as f32;
+        match interpolation {
+            Interpolation::Hsv => Interpolation::HsvShortest.mix(a, b, t),
+            _ => interpolation.mix(a, b, t),
+        }
+    }
+
+    /// A copy with the value of every stop multiplied by `brightness`.
+    pub fn scaled(&self, brightness: f32) -> Palette {
+        let mut out = self.clone();
+        for stop in out.stops.iter_mut() {
+            stop.set_v(stop.get_v() * brightness);
+        }
+        out
+    }
+}
+
+/// Every palette by name, the built-in ones plus those from the config file or `/palettes`.
+#[derive(Debug, Clone)]
+pub struct Palettes {
+    palettes: BTreeMap<String, Palette>,
+}
+
+impl Palettes {
+    pub fn new() -> Self {
+        let mut palettes = BTreeMap::new();
+        for (name, palette) in builtin() {
+            palettes.insert(String::from(name), palette);
+        }
+        Self { palettes }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Palette> {
+        self.palettes.get(name)
+    }
+
+    pub fn list(&self) -> &BTreeMap<String, Palette> {
+        &self.palettes
+    }
+
+    /// The palette named by the `palette` param, rainbow if it isn't set or doesn't exist.
+    pub fn resolve(&self, params: &Params) -> &Palette {
+        self.get(params.get_str("palette", "rainbow"))
+            .or_else(|| self.get("rainbow"))
+            .expect("rainbow palette is built in")
+    }
+
+    /// Checks that the `palette` param, if set, names an existing palette.
+    pub fn check(&self, params: &Params) -> Result<(), String> {
+        match params.get("palette") {
+            Some(_) if self.get(params.get_str("palette", "")).is_none() => Err(String::from("unknown palette")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, palette: Palette) -> Result<(), &'static str> {
+        if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err("palette names are 1 to 32 letters, digits, '_' or '-'");
+        }
+        if is_builtin(name) {
+            return Err("built-in palettes can't be changed");
+        }
+        palette.validate()?;
+        if !self.palettes.contains_key(name) && self.palettes.len() >= builtin().len() + MAX_USER {
+            return Err("too many palettes");
+        }
+        self.palettes.insert(String::from(name), palette);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), &'static str> {
+        if is_builtin(name) {
+            return Err("built-in palettes can't be changed");
+        }
+        match self.palettes.remove(name) {
+            Some(_) => Ok(()),
+            None => Err("unknown palette"),
+        }
+    }
+
+    /// Adds the user palettes saved by `save`, if there are any.
+    pub fn load(&mut self) {
+        let saved: BTreeMap<String, Palette> = match fs::read_to_string(USER_FILE) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+            Err(_) => return,
+        };
+        for (name, palette) in saved {
+            let _ = self.set(&name, palette);
+        }
+    }
+
+    pub fn save(&self) {
+        let user: BTreeMap<&String, &Palette> = self.palettes.iter().filter(|(name, _)| !is_builtin(name)).collect();
+        if let Ok(json) = serde_json::to_string(&user) {
+            let _ = fs::write(USER_FILE, json);
+        }
+    }
+}
+
+fn is_builtin(name: &str) -> bool {
+    builtin().iter().any(|(n, _)| *n == name)
+}
+
+pub fn builtin() -> Vec<(&'static str, Palette)> {
+    vec![
+        ("rainbow", Palette::from_hsv(&[
+            (0.0, 1.0, 1.0), (0.125, 1.0, 1.0), (0.25, 1.0, 1.0), (0.375, 1.0, 1.0),
+            (0.5, 1.0, 1.0), (0.625, 1.0, 1.0), (0.75, 1.0, 1.0), (0.875, 1.0, 1.0),
+        ])),
+        ("ocean", Palette::from_hsv(&[
+            (0.66, 1.0, 0.4), (0.6, 1.0, 0.8), (0.5, 0.8, 1.0), (0.45, 1.0, 0.6), (0.65, 1.0, 0.3),
+        ])),
+        ("lava", Palette::from_hsv(&[
+            (0.0, 1.0, 0.0), (0.0, 1.0, 0.5), (0.0, 1.0, 1.0), (0.08, 1.0, 1.0), (0.15, 0.8, 1.0), (0.0, 1.0, 0.8),
+        ])),
+        ("forest", Palette::from_hsv(&[
+            (0.33, 1.0, 0.3), (0.3, 0.8, 0.7), (0.2, 0.9, 0.5), (0.25, 0.7, 0.9), (0.4, 0.7, 0.5),
+        ])),
+        ("party", Palette::from_hsv(&[
+            (0.8, 1.0, 1.0), (0.65, 1.0, 1.0), (0.9, 0.9, 1.0), (0.08, 1.0, 1.0), (0.15, 1.0, 1.0), (0.85, 1.0, 1.0),
+        ])),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ParamValue;
+
+    #[test]
+    fn test_palettes() {
+        let rainbow = Palettes::new().resolve(&Params::new()).clone();
+
+        // -test- stops are spread evenly and the palette wraps around
+        assert_eq!(rainbow.at(0.0, Interpolation::Hsv), ColorHsv::new(0.0, 1.0, 1.0));
+        assert_eq!(rainbow.at(0.5, Interpolation::Hsv), ColorHsv::new(0.5, 1.0, 1.0));
+        assert_eq!(rainbow.at(1.25, Interpolation::Hsv), rainbow.at(0.25, Interpolation::Hsv));
+
+        // -test- hue crosses from the last stop back to the first the short way
+        let h = rainbow.at(0.96, Interpolation::Hsv).get_h();
+        assert!(h > 0.875 && h < 1.0);
+
+        // -test- user palettes can be added and removed, built-in ones can't be changed
+        let mut palettes = Palettes::new();
+        let two = Palette::new(vec![ColorHsv::new(0.0, 1.0, 1.0), ColorHsv::new(0.5, 1.0, 1.0)]);
+        assert!(palettes.set("mine", two.clone()).is_ok());
+        assert!(palettes.set("ocean", two.clone()).is_err());
+        assert!(palettes.set("bad name", two.clone()).is_err());
+        assert!(palettes.set("short", Palette::new(vec![ColorHsv::default()])).is_err());
+        let params = Params::new().with("palette", ParamValue::Text(String::from("mine")));
+        assert_eq!(palettes.resolve(&params), &two);
+        assert!(palettes.check(&params).is_ok());
+        assert!(palettes.remove("mine").is_ok());
+        assert!(palettes.check(&params).is_err());
+        assert!(palettes.remove("lava").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::colors::ColorHsv;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Number(f32),
+    Text(String),
+    Color(ColorHsv),
+}
+
+/// Named pattern parameters such as `speed` or `count`, set per device through `/set/{id}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Params(BTreeMap<String, ParamValue>);
+
+impl Params {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: &str, value: ParamValue) -> Self {
+        self.0.insert(String::from(name), value);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ParamValue> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ParamValue)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Overwrites the values in `self` with those set in `other`.
+    pub fn merge(&mut self, other: &Params) {
+        for (name, value) in &other.0 {
+            self.0.insert(name.clone(), value.clone());
+        }
+    }
+
+    pub fn get_f32(&self, name: &str, default: f32) -> f32 {
+        match self.0.get(name) {
+            Some(ParamValue::Number(v)) if v.is_finite() => *v,
+            Some(ParamValue::Bool(v)) => if *v { 1.0 } else { 0.0 },
+            _ => default,
+        }
+    }
+
+    pub fn get_usize(&self, name: &str, default: usize) -> usize {
+        match self.0.get(name) {
+            Some(ParamValue::Number(v)) if v.is_finite() => v.max(0.0).round() as usize,
+            _ => default,
+        }
+    }
+
+    pub fn get_bool(&self, name: &str, default: bool) -> bool {
+        match self.0.get(name) {
+            Some(ParamValue::Bool(v)) => *v,
+            _ => default,
+        }
+    }
+
+    pub fn get_str<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
+        match self.0.get(name) {
+            Some(ParamValue::Text(v)) => v,
+            _ => default,
+        }
+    }
+
+    /// `1.0` for a forward `direction`, `-1.0` for reverse.
+    pub fn get_direction(&self) -> f32 {
+        match self.get_str("direction", "forward") {
+            "reverse" => -1.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params() {
+        let mut params = Params::new()
+            .with("speed", ParamValue::Number(0.5))
+            .with("count", ParamValue::Number(2.0));
+        let set: Params = serde_json::from_str(r#"{"speed": 0.25, "direction": "reverse", "mirror": true}"#).unwrap();
+        params.merge(&set);
+
+        // -test- values set on the device override the defaults
+        assert_eq!(params.get_f32("speed", 0.0), 0.25);
+        assert_eq!(params.get_usize("count", 0), 2);
+        assert_eq!(params.get_f32("mirror", 0.0), 1.0);
+        assert!(params.get_bool("mirror", false));
+        assert_eq!(params.get_direction(), -1.0);
+
+        // -test- missing or mistyped values fall back to the given default
+        assert_eq!(params.get_f32("scale", 3.0), 3.0);
+        assert_eq!(params.get_str("speed", "fast"), "fast");
+        assert!(params.get_bool("speed", true));
+        assert_eq!(Params::new().get_direction(), 1.0);
+    }
+}
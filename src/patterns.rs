@@ -0,0 +1,673 @@
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{Animation, Frame};
+use crate::alerts::MAX_TEXT;
+use crate::colors::{ColorHsv, MAX_KELVIN, MIN_KELVIN};
+use crate::font;
+use crate::leds::{FireLayout, Leds};
+use crate::maps::{PixelMap, Point};
+use crate::noise::NoiseField;
+use crate::palettes::Palette;
+use crate::params::{ParamValue, Params};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    Color,
+    Float,
+    Int,
+    Bool,
+    Enum,
+    // name of a palette from `/palettes`
+    Palette,
+    // name of a script from `/scripts`
+    Script,
+    // name of an image or animation from `/media`
+    Media,
+    // free text of up to `max` characters
+    Text,
+}
+
+/// Declaration of one pattern parameter, served by `/patterns` so the UI can build a
+/// control for it.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<&'static str>>,
+    pub default: ParamValue,
+}
+
+impl ParamSpec {
+    // colors are stored in `color1`..`color3` of the state, the spec only marks them as used
+    pub fn color(name: &'static str) -> Self {
+        Self { name, kind: ParamType::Color, min: None, max: None, options: None, default: ParamValue::Color(ColorHsv::default()) }
+    }
+
+    pub fn float(name: &'static str, min: f32, max: f32, default: f32) -> Self {
+        Self { name, kind: ParamType::Float, min: Some(min), max: Some(max), options: None, default: ParamValue::Number(default) }
+    }
+
+    pub fn int(name: &'static str, min: u32, max: u32, default: u32) -> Self {
+        Self { name, kind: ParamType::Int, min: Some(min as f32), max: Some(max as f32), options: None, default: ParamValue::Number(default as f32) }
+    }
+
+    pub fn boolean(name: &'static str, default: bool) -> Self {
+        Self { name, kind: ParamType::Bool, min: None, max: None, options: None, default: ParamValue::Bool(default) }
+    }
+
+    pub fn choice(name: &'static str, options: &[&'static str], default: &'static str) -> Self {
+        Self { name, kind: ParamType::Enum, min: None, max: None, options: Some(options.to_vec()), default: ParamValue::Text(String::from(default)) }
+    }
+
+    // the palette is checked against the current palettes when it is set
+    pub fn palette(name: &'static str, default: &'static str) -> Self {
+        Self { name, kind: ParamType::Palette, min: None, max: None, options: None, default: ParamValue::Text(String::from(default)) }
+    }
+
+    // the script is checked against the current scripts when it is set
+    pub fn script(name: &'static str) -> Self {
+        Self { name, kind: ParamType::Script, min: None, max: None, options: None, default: ParamValue::Text(String::new()) }
+    }
+
+    // the media is checked against the current media when it is set
+    pub fn media(name: &'static str) -> Self {
+        Self { name, kind: ParamType::Media, min: None, max: None, options: None, default: ParamValue::Text(String::new()) }
+    }
+
+    pub fn text(name: &'static str, max: usize, default: &'static str) -> Self {
+        Self { name, kind: ParamType::Text, min: None, max: Some(max as f32), options: None, default: ParamValue::Text(String::from(default)) }
+    }
+
+    /// Checks that the default is a valid value, for specs that don't come from this file.
+    pub fn check_default(&self) -> Result<(), String> {
+        self.validate(&self.default)
+    }
+
+    fn validate(&self, value: &ParamValue) -> Result<(), String> {
+        let valid = match (self.kind, value) {
+            (ParamType::Color, ParamValue::Color(_)) => true,
+            (ParamType::Float, ParamValue::Number(v)) => self.in_range(*v),
+            (ParamType::Int, ParamValue::Number(v)) => v.fract() == 0.0 && self.in_range(*v),
+            (ParamType::Bool, ParamValue::Bool(_)) => true,
+            (ParamType::Enum, ParamValue::Text(v)) => self.options.as_ref().is_some_and(|o| o.contains(&v.as_str())),
+            (ParamType::Palette | ParamType::Script | ParamType::Media, ParamValue::Text(_)) => true,
+            (ParamType::Text, ParamValue::Text(v)) => self.in_range(v.chars().count() as f32),
+            _ => false,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid value for {}", self.name))
+        }
+    }
+
+    fn in_range(&self, value: f32) -> bool {
+        value >= self.min.unwrap_or(f32::MIN) && value <= self.max.unwrap_or(f32::MAX)
+    }
+}
+
+// plugins are numbered from here up, leaving the numbers below for built-in patterns
+pub const FIRST_PLUGIN: u8 = 128;
+
+// flashes per second, kept at or below the 3 Hz limit of photosensitivity guidelines
+pub const MAX_STROBE_RATE: f32 = 3.0;
+
+fn colors() -> Vec<ParamSpec> {
+    vec![ParamSpec::color("color1"), ParamSpec::color("color2"), ParamSpec::color("color3")]
+}
+
+fn scrolling(scale: f32, max_scale: f32) -> Vec<ParamSpec> {
+    vec![
+        ParamSpec::float("speed", 0.0, 0.02, 0.0004),
+        ParamSpec::choice("direction", &["forward", "reverse"], "forward"),
+        ParamSpec::float("scale", 0.1, max_scale, scale),
+    ]
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PatternInfo {
+    pub id: u8,
+    pub name: &'static str,
+    pub params: Vec<ParamSpec>,
+}
+
+impl PatternInfo {
+    fn new(id: u8, name: &'static str, params: Vec<Vec<ParamSpec>>) -> Self {
+        Self { id, name, params: params.concat() }
+    }
+
+    /// The default value of every declared parameter.
+    pub fn defaults(&self) -> Params {
+        self.params.iter().fold(Params::new(), |p, spec| p.with(spec.name, spec.default.clone()))
+    }
+
+    /// The defaults overridden by the values in `set` that this pattern declares, so params
+    /// left over from other patterns don't leak in.
+    pub fn resolve(&self, set: &Params) -> Params {
+        let mut out = self.defaults();
+        for (name, value) in set.iter().filter(|(name, _)| self.params.iter().any(|spec| spec.name == *name)) {
+            out = out.with(name, value.clone());
+        }
+        out
+    }
+
+    /// Whether the pattern scrolls with the `speed`, `direction` and `scale` params.
+    pub fn is_scrolling(&self) -> bool {
+        self.params.iter().any(|spec| spec.name == "scale")
+    }
+
+    /// Replaces the default of every parameter named in `defaults`, e.g. with values from the
+    /// config file.
+    pub fn set_defaults(&mut self, defaults: &Params) {
+        for spec in self.params.iter_mut() {
+            if let Some(value) = defaults.get(spec.name) {
+                spec.default = value.clone();
+            }
+        }
+    }
+
+    /// Checks that every value in `params` is declared by this pattern and has the right type
+    /// and range. Colors come from the device state, so they can't be set as params.
+    pub fn validate(&self, params: &Params) -> Result<(), String> {
+        for (name, value) in params.iter() {
+            match self.params.iter().find(|spec| spec.name == name) {
+                Some(spec) if spec.kind == ParamType::Color => return Err(format!("{} is set with the device colors, not params", name)),
+                Some(spec) => spec.validate(value)?,
+                None => return Err(format!("{} has no parameter {}", self.name, name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The pattern numbered `id` in `patterns`.
+pub fn find(patterns: &[PatternInfo], id: u8) -> Option<&PatternInfo> {
+    patterns.iter().find(|info| info.id == id)
+}
+
+/// Every built-in pattern, indexed by pattern number.
+pub fn builtin() -> Vec<PatternInfo> {
+    vec![
+        PatternInfo::new(0, "off", vec![]),
+        PatternInfo::new(1, "gradient", vec![colors()]),
+        PatternInfo::new(2, "sine wave", vec![colors(), scrolling(1.0, 10.0)]),
+        PatternInfo::new(3, "random sprites", vec![colors(), vec![
+            ParamSpec::int("count", 0, 64, 1),
+            ParamSpec::float("falloff", 1.0, 50.0, 4.0),
+            ParamSpec::float("max_speed", 0.0001, 0.05, 0.01),
+        ]]),
+        PatternInfo::new(4, "plane sweep", vec![colors(), scrolling(1.0, 10.0), vec![
+            ParamSpec::choice("axis", &["x", "y", "z"], "y"),
+        ]]),
+        PatternInfo::new(5, "spherical pulse", vec![colors(), scrolling(1.0, 10.0)]),
+        PatternInfo::new(6, "noise field", vec![colors(), scrolling(3.0, 20.0), vec![
+            ParamSpec::int("octaves", 1, 6, 1),
+        ]]),
+        PatternInfo::new(7, "fire", vec![colors(), vec![
+            ParamSpec::int("cooling", 20, 100, 55),
+            ParamSpec::int("sparking", 50, 200, 120),
+            ParamSpec::choice("layout", &["bottom", "top", "mirrored", "centered"], "bottom"),
+        ]]),
+        PatternInfo::new(8, "twinkle", vec![colors(), vec![
+            ParamSpec::float("density", 0.0, 0.2, 0.01),
+            ParamSpec::float("fade", 0.002, 0.2, 0.02),
+            ParamSpec::int("seed", 0, 65535, 0),
+        ]]),
+        PatternInfo::new(9, "comets", vec![colors(), vec![
+            ParamSpec::int("count", 1, 16, 1),
+            ParamSpec::float("length", 0.01, 1.0, 0.2),
+            ParamSpec::float("speed", 0.0, 0.05, 0.005),
+            ParamSpec::choice("direction", &["forward", "reverse"], "forward"),
+            ParamSpec::float("decay", 0.2, 8.0, 2.0),
+            ParamSpec::choice("ends", &["wrap", "bounce"], "wrap"),
+        ]]),
+        PatternInfo::new(10, "rainbow", vec![scrolling(1.0, 10.0)]),
+        PatternInfo::new(11, "palette", vec![scrolling(1.0, 10.0), vec![
+            ParamSpec::palette("palette", "party"),
+        ]]),
+        PatternInfo::new(12, "palette noise", vec![scrolling(3.0, 20.0), vec![
+            ParamSpec::int("octaves", 1, 6, 1),
+            ParamSpec::palette("palette", "ocean"),
+        ]]),
+        PatternInfo::new(13, "breathing", vec![colors(), vec![
+            ParamSpec::float("period", 0.5, 30.0, 4.0),
+        ]]),
+        PatternInfo::new(14, "strobe", vec![colors(), vec![
+            ParamSpec::float("rate", 0.1, MAX_STROBE_RATE, 2.0),
+            ParamSpec::float("duty", 0.05, 0.5, 0.1),
+        ]]),
+        PatternInfo::new(15, "theater chase", vec![colors(), vec![
+            ParamSpec::float("rate", 0.5, 30.0, 5.0),
+            ParamSpec::choice("direction", &["forward", "reverse"], "forward"),
+            ParamSpec::int("spacing", 2, 10, 3),
+        ]]),
+        PatternInfo::new(16, "color wipe", vec![colors(), vec![
+            ParamSpec::float("period", 0.5, 30.0, 3.0),
+            ParamSpec::choice("direction", &["forward", "reverse"], "forward"),
+        ]]),
+        PatternInfo::new(17, "bouncing balls", vec![colors(), vec![
+            ParamSpec::int("count", 1, 16, 3),
+            ParamSpec::float("gravity", 0.5, 20.0, 9.8),
+            ParamSpec::float("restitution", 0.5, 0.95, 0.85),
+            ParamSpec::float("falloff", 1.0, 50.0, 20.0),
+        ]]),
+        PatternInfo::new(18, "cellular automaton", vec![colors(), vec![
+            ParamSpec::int("rule", 0, 255, 30),
+            ParamSpec::int("frames", 1, 60, 5),
+            ParamSpec::int("seed", 0, 65535, 0),
+        ]]),
+        PatternInfo::new(19, "clock", vec![colors(), vec![
+            ParamSpec::float("start", 0.0, 1.0, 0.0),
+            ParamSpec::choice("direction", &["forward", "reverse"], "forward"),
+            ParamSpec::boolean("seconds", true),
+        ]]),
+        PatternInfo::new(20, "day progress", vec![colors(), vec![
+            ParamSpec::float("sunrise", 0.0, 24.0, 6.5),
+            ParamSpec::float("sunset", 0.0, 24.0, 19.5),
+        ]]),
+        PatternInfo::new(21, "meter", vec![colors(), vec![
+            ParamSpec::float("value", 0.0, 1.0, 0.0),
+            ParamSpec::float("smoothing", 0.0, 5.0, 0.3),
+            ParamSpec::float("warn", 0.0, 1.0, 0.6),
+            ParamSpec::float("alarm", 0.0, 1.0, 0.85),
+            ParamSpec::float("peak_hold", 0.0, 10.0, 1.5),
+            ParamSpec::choice("direction", &["forward", "reverse"], "forward"),
+        ]]),
+        PatternInfo::new(22, "spectrum bars", vec![colors(), vec![
+            ParamSpec::float("gain", 0.1, 10.0, 1.0),
+        ]]),
+        PatternInfo::new(23, "beat flash", vec![colors(), vec![
+            ParamSpec::float("decay", 0.05, 2.0, 0.3),
+        ]]),
+        PatternInfo::new(24, "script", vec![vec![
+            ParamSpec::script("script"),
+            ParamSpec::float("p1", 0.0, 1.0, 0.5),
+            ParamSpec::float("p2", 0.0, 1.0, 0.5),
+            ParamSpec::float("p3", 0.0, 1.0, 0.5),
+        ]]),
+        PatternInfo::new(25, "image", vec![vec![
+            ParamSpec::media("media"),
+            ParamSpec::float("speed", 0.1, 10.0, 1.0),
+            ParamSpec::int("loops", 0, 100, 0),
+        ]]),
+        PatternInfo::new(26, "text", vec![vec![
+            ParamSpec::color("color1"),
+            ParamSpec::color("color2"),
+            ParamSpec::text("text", MAX_TEXT, "Hello"),
+            ParamSpec::float("rate", 1.0, 100.0, 20.0),
+            ParamSpec::int("loops", 0, 100, 0),
+        ]]),
+        PatternInfo::new(27, "tunable white", vec![vec![
+            ParamSpec::int("temperature", MIN_KELVIN as u32, MAX_KELVIN as u32, 2700),
+            ParamSpec::float("brightness", 0.0, 1.0, 1.0),
+        ]]),
+    ]
+}
+
+// length of the fire simulation, halved when the flames are drawn twice
+fn fire_len(params: &Params, len: usize) -> usize {
+    match params.get_str("layout", "bottom") {
+        "mirrored" | "centered" => len.div_ceil(2) + 1,
+        _ => len,
+    }
+}
+
+/// Advances the animation of `pattern` on the device laid out by `map` to `frame`.
+pub fn step(pattern: u8, params: &Params, anim: &mut Animation, map: &PixelMap, frame: &Frame) {
+    let len = map.len();
+    let dt = frame.dt;
+    anim.update(params, frame);
+    match pattern {
+        3 => anim.update_sprites(params),
+        7 => anim.update_fire(params, fire_len(params, len)),
+        8 => anim.update_twinkles(params, len),
+        9 => anim.update_comets(params),
+        17 => anim.update_balls(params, dt),
+        18 => anim.update_automaton(params, map),
+        21 => anim.update_meter(params, dt),
+        23 => anim.update_flash(params, dt),
+        24 => anim.update_script(params, len, frame),
+        25 => anim.update_media(params, map, frame),
+        26 => anim.update_text(params, dt),
+        FIRST_PLUGIN..=u8::MAX => anim.update_plugin(pattern, params, len, frame),
+        _ => (),
+    }
+}
+
+/// Draws one frame of `pattern`. `palette` is the one named by the params, or rainbow.
+pub fn render(led: &mut Leds, pattern: u8, cols: &[ColorHsv; 3], palette: &Palette, params: &Params, map: &PixelMap, anim: &Animation) {
+    let offset = anim.get_offset();
+    let scale = params.get_f32("scale", 1.0);
+    let field = NoiseField { scale, time: anim.get_travel() * 10.0, octaves: params.get_usize("octaves", 1).min(8) };
+    match pattern {
+        // all LEDs off
+        0 => led.all_off(),
+        // fixed gradient
+        1 => led.fill_gradient_triple(&cols[0], &cols[1], &cols[2]),
+        // scrolling sine wave
+        2 => led.fill_sine(&cols[0], &cols[1], &cols[2], offset, scale),
+        // random sprites
+        3 => led.fill_sprites(&cols[0], &cols[1], &cols[2], anim.get_sprites()),
+        // plane sweeping through the pixel map, upward unless another axis is set
+        4 => {
+            let wave = match params.get_str("axis", "y") {
+                "x" => Point::new(scale, 0.0, 0.0),
+                "z" => Point::new(0.0, 0.0, scale),
+                _ => Point::new(0.0, scale, 0.0),
+            };
+            led.fill_plane(&cols[0], &cols[1], &cols[2], map, wave, offset);
+        }
+        // spherical pulse from the center of the pixel map
+        5 => led.fill_sphere(&cols[0], &cols[1], &cols[2], map, offset, scale),
+        // 3D noise field sampled at the pixel map, drifting 10 units per unit of scroll
+        6 => led.fill_noise(&cols[0], &cols[1], &cols[2], map, &field),
+        // Fire2012 flame simulation
+        7 => {
+            let layout = match params.get_str("layout", "bottom") {
+                "top" => FireLayout::Top,
+                "mirrored" => FireLayout::Mirrored,
+                "centered" => FireLayout::Centered,
+                _ => FireLayout::Bottom,
+            };
+            led.fill_fire(&cols[0], &cols[1], &cols[2], anim.get_fire().get_heat(), layout);
+        }
+        // random twinkles fading in and out on a dark background
+        8 => led.fill_twinkle(&cols[0], &cols[1], &cols[2], anim.get_twinkles()),
+        // comets with a head in the first color and a tail fading from the second to the third
+        9 => led.fill_comets(&cols[0], &cols[1], &cols[2], anim.get_comets(), params.get_f32("decay", 2.0)),
+        // scrolling palette, rainbow unless another palette is set
+        10 | 11 => led.fill_palette(palette, offset, scale),
+        // noise field like 6, colored from a palette
+        12 => led.fill_noise_palette(palette, map, &field),
+        // sinusoidal fade from the first color to the second and back
+        13 => {
+            let phase = anim.get_time() / params.get_f32("period", 4.0).max(0.1);
+            let level = (1.0 - (phase * std::f32::consts::TAU).cos()) / 2.0;
+            led.fill_solid(&led.mix(&cols[0], &cols[1], level));
+        }
+        // flashes of the first color over the second
+        14 => {
+            let rate = params.get_f32("rate", 2.0).clamp(0.0, MAX_STROBE_RATE);
+            let phase = (anim.get_time() * rate).fract();
+            let on = rate > 0.0 && phase < params.get_f32("duty", 0.1);
+            led.fill_solid(if on { &cols[0] } else { &cols[1] });
+        }
+        // every `spacing`th pixel in the first color, marching `rate` pixels per second
+        15 => {
+            let step = (anim.get_time() * params.get_f32("rate", 5.0)) as i64 * params.get_direction() as i64;
+            led.fill_theater(&cols[0], &cols[1], step, params.get_usize("spacing", 3).max(1));
+        }
+        // each color in turn wiping over the one before, one wipe every `period` seconds
+        16 => {
+            let progress = anim.get_time() / params.get_f32("period", 3.0).max(0.1);
+            let current = progress as usize % 3;
+            let reverse = params.get_direction() < 0.0;
+            led.fill_wipe(&cols[(current + 2) % 3], &cols[current], progress.fract(), reverse);
+        }
+        // balls bouncing on the first LED, colored in turn from the three colors
+        17 => led.fill_balls(&cols[0], &cols[1], &cols[2], anim.get_balls(), params.get_f32("falloff", 20.0)),
+        // cells fading from the first color when dead to the second when alive
+        18 => {
+            let (automaton, progress) = anim.get_automaton();
+            led.fill_automaton(&cols[0], &cols[1], automaton, progress, map);
+        }
+        // hour, minute and second hands in the three colors, around a ring or the face of the map
+        // starting at `start`
+        19 => {
+            let time = anim.get_local_time().time();
+            let seconds = time.second() as f32 + time.nanosecond() as f32 / 1e9;
+            let minutes = time.minute() as f32 + seconds / 60.0;
+            let hours = (time.hour() % 12) as f32 + minutes / 60.0;
+            let mut hands = vec![(cols[0], hours / 12.0), (cols[1], minutes / 60.0)];
+            if params.get_bool("seconds", true) {
+                hands.push((cols[2], seconds / 60.0));
+            }
+            let start = params.get_f32("start", 0.0);
+            let hands: Vec<(ColorHsv, f32)> = hands.into_iter().map(|(c, p)| (c, start + p * params.get_direction())).collect();
+            led.fill_hands(&hands, map);
+        }
+        // bar filling from sunrise to sunset, in a gradient from the first color through the
+        // second to the third, and dark at night
+        20 => {
+            let time = anim.get_local_time().time();
+            let hours = time.num_seconds_from_midnight() as f32 / 3600.0;
+            let sunrise = params.get_f32("sunrise", 6.5);
+            let sunset = params.get_f32("sunset", 19.5);
+            let progress = if sunset > sunrise { (hours - sunrise) / (sunset - sunrise) } else { -1.0 };
+            if (0.0..=1.0).contains(&progress) {
+                led.fill_bar(&cols[0], &cols[1], &cols[2], progress);
+            } else {
+                led.all_off();
+            }
+        }
+        // bar filled to the meter level, in the first color up to `warn`, the second up to
+        // `alarm` and the third above, with a marker at the peak unless `peak_hold` is 0
+        21 => {
+            let meter = anim.get_meter();
+            let peak = if params.get_f32("peak_hold", 1.5) > 0.0 { Some(meter.get_peak()) } else { None };
+            let thresholds = (params.get_f32("warn", 0.6), params.get_f32("alarm", 0.85));
+            led.fill_meter(cols, meter.get_level(), peak, thresholds, params.get_direction() < 0.0);
+        }
+        // one bar per audio band, low to high in colors running from the first to the third;
+        // bars grow upward on a matrix and brighten along a strip
+        22 => {
+            let gain = params.get_f32("gain", 1.0);
+            let bands: Vec<f32> = anim.get_audio().bands.iter().map(|b| (b * gain).min(1.0)).collect();
+            match map.get_matrix() {
+                Some(matrix) => led.fill_bars(cols, &bands, matrix),
+                None => led.fill_bands(cols, &bands),
+            }
+        }
+        // the second color flashed over the first on every beat
+        23 => led.fill_solid(&led.mix(&cols[0], &cols[1], anim.get_flash())),
+        // colors from a user script, see scripts.rs
+        24 => led.fill_pixels(anim.get_pixels()),
+        // image or animation from `/media`, see media.rs
+        25 => led.fill_pixels(anim.get_pixels()),
+        // text in the first color over the second, scrolling in from the right `rate` pixels
+        // per second and gone after `loops` passes unless that is 0
+        26 => {
+            let columns = font::columns(params.get_str("text", ""));
+            let width = map.get_matrix().map_or(map.len(), |m| m.width);
+            let cycle = (columns.len() + width) as f32;
+            let loops = params.get_f32("loops", 0.0);
+            let scroll = anim.get_scroll();
+            let scroll = if loops == 0.0 || scroll < loops * cycle { scroll % cycle } else { cycle };
+            led.fill_text(&cols[0], &cols[1], &columns, scroll as usize, map);
+        }
+        // white light at `temperature` Kelvin
+        27 => {
+            let level = params.get_f32("brightness", 1.0) * anim.get_brightness();
+            led.fill_solid(&ColorHsv::from_kelvin(params.get_f32("temperature", 2700.0), level));
+        }
+        // colors from a plugin, see plugins.rs
+        FIRST_PLUGIN..=u8::MAX => led.fill_pixels(anim.get_pixels()),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::ColorRgb;
+
+    #[test]
+    fn test_pattern_schema() {
+        let patterns = builtin();
+        // -test- pattern numbers match their position, below the plugins
+        assert!(patterns.iter().enumerate().all(|(i, p)| p.id as usize == i));
+        assert!(patterns.len() <= FIRST_PLUGIN as usize);
+        assert_eq!(find(&patterns, 6).map(|p| p.name), Some("noise field"));
+
+        let noise = &patterns[6];
+        assert_eq!(noise.defaults().get_f32("scale", 0.0), 3.0);
+
+        let ok: Params = serde_json::from_str(r#"{"speed": 0.001, "direction": "reverse"}"#).unwrap();
+        assert!(noise.validate(&ok).is_ok());
+        let out_of_range: Params = serde_json::from_str(r#"{"scale": 100}"#).unwrap();
+        assert!(noise.validate(&out_of_range).is_err());
+        let bad_option: Params = serde_json::from_str(r#"{"direction": "up"}"#).unwrap();
+        assert!(noise.validate(&bad_option).is_err());
+        let unknown: Params = serde_json::from_str(r#"{"count": 3}"#).unwrap();
+        assert!(noise.validate(&unknown).is_err());
+        let color: Params = serde_json::from_str(r#"{"color1": "red"}"#).unwrap();
+        assert!(noise.validate(&color).is_err());
+        let stale = Params::new().with("palette", ParamValue::Text(String::from("ocean")));
+        assert!(patterns[10].resolve(&stale).get("palette").is_none());
+        assert_eq!(patterns[11].resolve(&stale).get_str("palette", ""), "ocean");
+
+        let fractional: Params = serde_json::from_str(r#"{"count": 2.5}"#).unwrap();
+        assert!(patterns[3].validate(&fractional).is_err());
+    }
+
+    #[test]
+    fn test_basic_effects() {
+        let map = PixelMap::linear(6);
+        let red = ColorHsv::new(0.0, 1.0, 1.0);
+        let black = ColorHsv::new(0.0, 0.0, 0.0);
+        let cols = [red, black, black];
+        let palette = Palette::new(vec![red, black]);
+        let mut led = Leds::new(6, crate::leds::LedType::Ws2801);
+        let lit = |led: &Leds| (0..6).map(|i| led.get_led(i) == Some(red.to_rgb())).collect::<Vec<_>>();
+
+        // -test- strobes faster than the safe rate are rejected
+        let fast = Params::new().with("rate", ParamValue::Number(10.0));
+        assert!(builtin()[14].validate(&fast).is_err());
+
+        // -test- the theater chase marches one pixel per step
+        let params = builtin()[15].defaults();
+        let mut anim = Animation::new();
+        render(&mut led, 15, &cols, &palette, &params, &map, &anim);
+        assert_eq!(lit(&led), [true, false, false, true, false, false]);
+        step(15, &params, &mut anim, &map, &Frame { dt: 0.2, ..Default::default() });
+        render(&mut led, 15, &cols, &palette, &params, &map, &anim);
+        assert_eq!(lit(&led), [false, true, false, false, true, false]);
+
+        // -test- the color wipe covers the strip from the start
+        let params = builtin()[16].defaults();
+        let mut anim = Animation::new();
+        step(16, &params, &mut anim, &map, &Frame { dt: 1.5, ..Default::default() });
+        render(&mut led, 16, &cols, &palette, &params, &map, &anim);
+        assert_eq!(lit(&led), [true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn test_tunable_white() {
+        let map = PixelMap::linear(6);
+        let black = ColorHsv::new(0.0, 0.0, 0.0);
+        let palette = Palette::new(vec![black, black]);
+        let mut led = Leds::new(6, crate::leds::LedType::Ws2801);
+
+        // -test- the temperature takes the same range as colors given in Kelvin
+        assert!(builtin()[27].validate(&Params::new().with("temperature", ParamValue::Number(20000.0))).is_ok());
+        assert!(builtin()[27].validate(&Params::new().with("temperature", ParamValue::Number(500.0))).is_err());
+
+        // -test- tunable white follows the temperature
+        let params = builtin()[27].defaults().with("temperature", ParamValue::Number(6600.0));
+        let mut anim = Animation::new();
+        step(27, &params, &mut anim, &map, &Frame { brightness: 1.0, ..Default::default() });
+        render(&mut led, 27, &[black; 3], &palette, &params, &map, &anim);
+        let white = led.get_led(0).unwrap();
+        assert!(white.get_r() == 255 && white.get_g() > 245 && white.get_b() > 245);
+        let params = params.with("temperature", ParamValue::Number(2000.0));
+        render(&mut led, 27, &[black; 3], &palette, &params, &map, &anim);
+        assert!(led.get_led(0).unwrap().get_b() < white.get_b() / 2);
+    }
+
+    #[test]
+    fn test_script_depth() {
+        let map = PixelMap::linear(2);
+        let black = ColorHsv::new(0.0, 0.0, 0.0);
+        let palette = Palette::new(vec![black, black]);
+        let mut led = Leds::new(2, crate::leds::LedType::Hd108);
+        let mut scripts = crate::scripts::Scripts::new();
+        assert!(scripts.set("dim", "fn render(i, t, len, params) { rgb(2, 2, 2) }").is_ok());
+        scripts.start_frame(std::time::Duration::from_secs(4));
+        let frame = Frame { brightness: 0.05, scripts: Some(std::sync::Arc::new(std::sync::Mutex::new(scripts))), ..Default::default() };
+        let params = builtin()[24].resolve(&Params::new().with("script", ParamValue::Text(String::from("dim"))));
+
+        // -test- a script color dimmed below one 8-bit step keeps its 16-bit level through to HD108
+        let mut anim = Animation::new();
+        step(24, &params, &mut anim, &map, &frame);
+        render(&mut led, 24, &[black; 3], &palette, &params, &map, &anim);
+        assert_eq!(led.get_pixel(0).map(|p| p.get_r()), Some(26));
+        assert_eq!(led.get_led(0), Some(ColorRgb::new(0, 0, 0)));
+        assert_eq!(led.encode()[16 + 2..16 + 4], [0, 26]);
+    }
+
+    #[test]
+    fn test_text() {
+        let map = PixelMap::grid(crate::maps::Matrix { width: 6, height: 9, serpentine: false });
+        let cols = [ColorHsv::new(0.0, 0.0, 1.0), ColorHsv::new(0.0, 0.0, 0.0), ColorHsv::new(0.0, 0.0, 0.0)];
+        let palette = Palette::new(vec![cols[0], cols[1]]);
+        let mut led = Leds::new(54, crate::leds::LedType::Ws2801);
+        let params = builtin()[26].resolve(&Params::new().with("text", ParamValue::Text(String::from("I"))).with("loops", ParamValue::Number(1.0)));
+        let lit = |led: &Leds| (0..6).map(|x| led.get_led(4 * 6 + x) == Some(cols[0].to_rgb())).collect::<Vec<_>>();
+
+        // -test- the text starts off the right edge and scrolls in a pixel at a time
+        let mut anim = Animation::new();
+        step(26, &params, &mut anim, &map, &Frame { dt: 0.0, ..Default::default() });
+        render(&mut led, 26, &cols, &palette, &params, &map, &anim);
+        assert_eq!(lit(&led), [false; 6]);
+        step(26, &params, &mut anim, &map, &Frame { dt: 0.15, ..Default::default() });
+        render(&mut led, 26, &cols, &palette, &params, &map, &anim);
+        assert_eq!(lit(&led), [false, false, false, false, false, true]);
+
+        // -test- the font is centered on the rows, and the display clears after the last loop
+        assert_eq!(led.get_led(5), Some(cols[1].to_rgb()));
+        assert_eq!(led.get_led(6 + 5), Some(cols[0].to_rgb()));
+        assert_eq!(led.get_led(8 * 6 + 5), Some(cols[1].to_rgb()));
+        step(26, &params, &mut anim, &map, &Frame { dt: 1.0, ..Default::default() });
+        render(&mut led, 26, &cols, &palette, &params, &map, &anim);
+        assert!((0..54).all(|i| led.get_led(i) == Some(cols[1].to_rgb())));
+    }
+
+    #[test]
+    fn test_clock() {
+        let map = PixelMap::linear(12);
+        let cols = [ColorHsv::new(0.0, 1.0, 1.0), ColorHsv::new(0.33, 1.0, 1.0), ColorHsv::new(0.66, 1.0, 1.0)];
+        let palette = Palette::new(vec![cols[0], cols[1]]);
+        let mut led = Leds::new(12, crate::leds::LedType::Ws2801);
+        let at = |h, m, s| chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap().and_hms_opt(h, m, s).unwrap();
+        let mut anim = Animation::new();
+
+        // -test- at 3:30:45 the hands point at 3.5, 6 and 9 o'clock
+        let params = builtin()[19].defaults();
+        step(19, &params, &mut anim, &map, &Frame { dt: 0.02, now: at(15, 30, 0), ..Default::default() });
+        render(&mut led, 19, &cols, &palette, &params, &map, &anim);
+        assert_eq!(led.get_led(6), Some(cols[1].to_rgb()));
+        assert_eq!(led.get_led(3).unwrap().get_r(), led.get_led(4).unwrap().get_r());
+        assert_eq!(led.get_led(1), Some(ColorRgb::new(0, 0, 0)));
+        step(19, &params, &mut anim, &map, &Frame { dt: 0.02, now: at(15, 30, 45), ..Default::default() });
+        render(&mut led, 19, &cols, &palette, &params, &map, &anim);
+        assert_eq!(led.get_led(9), Some(cols[2].to_rgb()));
+
+        // -test- on a matrix the hands run out from the center, 12 o'clock at the top
+        let grid = PixelMap::grid(crate::maps::Matrix { width: 5, height: 5, serpentine: false });
+        let mut face = Leds::new(25, crate::leds::LedType::Ws2801);
+        let params = params.with("seconds", ParamValue::Bool(false));
+        step(19, &params, &mut anim, &grid, &Frame { dt: 0.02, now: at(15, 0, 0), ..Default::default() });
+        render(&mut face, 19, &cols, &palette, &params, &grid, &anim);
+        assert!([13, 14].iter().all(|&i| face.get_led(i) == Some(cols[0].to_rgb())));
+        assert!([2, 7].iter().all(|&i| face.get_led(i) == Some(cols[1].to_rgb())));
+        assert!([0, 4, 20, 22, 24].iter().all(|&i| face.get_led(i) == Some(ColorRgb::new(0, 0, 0))));
+
+        // -test- the day bar is half full at noon and dark at night
+        let params = builtin()[20].defaults();
+        step(20, &params, &mut anim, &map, &Frame { dt: 0.02, now: at(13, 0, 0), ..Default::default() });
+        render(&mut led, 20, &cols, &palette, &params, &map, &anim);
+        assert!((0..6).all(|i| led.get_led(i) != Some(ColorRgb::new(0, 0, 0))));
+        assert!((6..12).all(|i| led.get_led(i) == Some(ColorRgb::new(0, 0, 0))));
+        step(20, &params, &mut anim, &map, &Frame { dt: 0.02, now: at(23, 0, 0), ..Default::default() });
+        render(&mut led, 20, &cols, &palette, &params, &map, &anim);
+        assert!((0..12).all(|i| led.get_led(i) == Some(ColorRgb::new(0, 0, 0))));
+    }
+}
@@ -0,0 +1,296 @@
+// pattern plugins compiled to WebAssembly, loaded from the plugins directory at startup and run
+// in a sandbox with no imports, a memory limit and a fuel limit per frame.
+//
+// A plugin exports its `memory` and three functions:
+//   frame_buffer(len: i32) -> i32    address of `len` * 3 bytes the plugin draws into, as RGB
+//   params_buffer(count: i32) -> i32 address of `count` f32 values the params are written to
+//   render(len: i32, time: f32)      draws a frame, `time` seconds into the animation
+// Params are written in the order they are declared, bools as 0 or 1 and enums as the index
+// of their option. `params_buffer` is only needed by plugins that declare params.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use wasmi::{Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::colors::ColorRgb;
+use crate::params::{ParamValue, Params};
+use crate::patterns::{ParamSpec, ParamType, PatternInfo, FIRST_PLUGIN};
+
+const DIR: &str = "plugins";
+// fuel is used up roughly one unit per instruction
+const FUEL_PER_FRAME: u64 = 5_000_000;
+const MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+/// The optional `<plugin>.json` next to a plugin, naming it and declaring its params in the
+/// same form `/patterns` lists them.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PluginSpec {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub params: Vec<PluginParam>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+    #[serde(default)]
+    pub options: Option<Vec<String>>,
+    pub default: ParamValue,
+}
+
+impl PluginParam {
+    // names are leaked to live as long as the built-in ones, which is only bounded because
+    // plugins are loaded once, by `Plugins::load` at startup
+    fn to_spec(&self) -> Result<ParamSpec, String> {
+        let name: &'static str = Box::leak(self.name.clone().into_boxed_str());
+        let options = self.options.as_ref().map(|o| o.iter().map(|v| &*Box::leak(v.clone().into_boxed_str())).collect::<Vec<_>>());
+        let spec = match self.kind {
+            ParamType::Float | ParamType::Int => ParamSpec { name, kind: self.kind, min: self.min, max: self.max, options: None, default: self.default.clone() },
+            ParamType::Bool => ParamSpec { name, kind: self.kind, min: None, max: None, options: None, default: self.default.clone() },
+            ParamType::Enum if options.as_ref().is_some_and(|o| !o.is_empty()) => ParamSpec { name, kind: self.kind, min: None, max: None, options, default: self.default.clone() },
+            _ => return Err(format!("param {} must be a float, int, bool or enum with options", self.name)),
+        };
+        spec.check_default()?;
+        Ok(spec)
+    }
+}
+
+#[derive(Debug)]
+struct Plugin {
+    info: PatternInfo,
+    module: Module,
+}
+
+/// Every plugin that loaded, numbered from `FIRST_PLUGIN` in file name order. Plugins can only
+/// be added as the set is loaded, and reloading them isn't supported: their names are leaked
+/// to give the `&'static str`s patterns are declared with.
+#[derive(Debug)]
+pub struct Plugins {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    fn new() -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Self { engine: Engine::new(&config), plugins: Vec::new() }
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &PatternInfo> {
+        self.plugins.iter().map(|p| &p.info)
+    }
+
+    fn get(&self, pattern: u8) -> Option<&Plugin> {
+        self.plugins.get(pattern.checked_sub(FIRST_PLUGIN)? as usize)
+    }
+
+    /// Compiles `wasm` and adds it as the next pattern, checking that it can be started and
+    /// has the exports it needs.
+    fn add(&mut self, name: &str, wasm: &[u8], spec: PluginSpec) -> Result<(), String> {
+        let id = FIRST_PLUGIN as usize + self.plugins.len();
+        if id > u8::MAX as usize {
+            return Err(String::from("too many plugins"));
+        }
+        let module = Module::new(&self.engine, wasm).map_err(|err| err.to_string())?;
+        let params = spec.params.iter().map(|p| p.to_spec()).collect::<Result<Vec<_>, _>>()?;
+        let name: &'static str = Box::leak(spec.name.unwrap_or_else(|| String::from(name)).into_boxed_str());
+        let plugin = Plugin { info: PatternInfo { id: id as u8, name, params }, module };
+        PluginInstance::new(&self.engine, &plugin)?;
+        self.plugins.push(plugin);
+        Ok(())
+    }
+
+    /// Every `.wasm` file in the plugins directory, reporting those that don't load. Called
+    /// once at startup.
+    pub fn load() -> Self {
+        let mut plugins = Self::new();
+        let mut paths: Vec<_> = match fs::read_dir(DIR) {
+            Ok(entries) => entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "wasm")).collect(),
+            Err(_) => return plugins,
+        };
+        paths.sort();
+        for path in paths {
+            let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+            let result = load_spec(&path.with_extension("json"))
+                .and_then(|spec| fs::read(&path).map_err(|err| err.to_string()).map(|wasm| (spec, wasm)))
+                .and_then(|(spec, wasm)| plugins.add(&name, &wasm, spec));
+            match result {
+                Ok(_) => println!("loaded plugin {} as pattern {}", path.display(), FIRST_PLUGIN as usize + plugins.plugins.len() - 1),
+                Err(err) => println!("error loading plugin {}: {}", path.display(), err),
+            }
+        }
+        plugins
+    }
+
+    /// A fresh copy of the plugin for `pattern`, with its own memory.
+    pub fn instantiate(&self, pattern: u8) -> Result<PluginInstance, String> {
+        match self.get(pattern) {
+            Some(plugin) => PluginInstance::new(&self.engine, plugin),
+            None => Err(format!("no plugin for pattern {}", pattern)),
+        }
+    }
+}
+
+fn load_spec(path: &Path) -> Result<PluginSpec, String> {
+    match fs::read_to_string(path) {
+        Ok(raw) => serde_json::from_str(&raw).map_err(|err| format!("{}: {}", path.display(), err)),
+        Err(_) => Ok(PluginSpec::default()),
+    }
+}
+
+/// One running copy of a plugin, kept by the animation that uses it.
+pub struct PluginInstance {
+    pattern: u8,
+    params: Vec<ParamSpec>,
+    store: Store<StoreLimits>,
+    memory: Memory,
+    frame_buffer: TypedFunc<i32, i32>,
+    params_buffer: Option<TypedFunc<i32, i32>>,
+    render: TypedFunc<(i32, f32), ()>,
+    // length and address of the frame buffer the plugin last handed out
+    frame: Option<(usize, usize)>,
+}
+
+impl std::fmt::Debug for PluginInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginInstance").field("pattern", &self.pattern).field("frame", &self.frame).finish()
+    }
+}
+
+impl PluginInstance {
+    fn new(engine: &Engine, plugin: &Plugin) -> Result<Self, String> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).instances(1).build();
+        let mut store = Store::new(engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL_PER_FRAME).map_err(|err| err.to_string())?;
+        // nothing is linked in, so a plugin that imports anything fails to start
+        let instance: Instance = Linker::new(engine)
+            .instantiate(&mut store, &plugin.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|err| err.to_string())?;
+        let memory = instance.get_memory(&store, "memory").ok_or("plugin must export its memory")?;
+        let export = |name: &str| format!("plugin must export {}", name);
+        let frame_buffer = instance.get_typed_func(&store, "frame_buffer").map_err(|_| export("frame_buffer(i32) -> i32"))?;
+        let render = instance.get_typed_func(&store, "render").map_err(|_| export("render(i32, f32)"))?;
+        let params_buffer = instance.get_typed_func(&store, "params_buffer").ok();
+        if params_buffer.is_none() && !plugin.info.params.is_empty() {
+            return Err(export("params_buffer(i32) -> i32"));
+        }
+        Ok(Self {
+            pattern: plugin.info.id,
+            params: plugin.info.params.clone(),
+            store,
+            memory,
+            frame_buffer,
+            params_buffer,
+            render,
+            frame: None,
+        })
+    }
+
+    /// Runs the plugin for a frame of `len` pixels, `time` seconds into the animation.
+    pub fn render(&mut self, len: usize, time: f32, params: &Params) -> Result<Vec<ColorRgb>, String> {
+        self.store.set_fuel(FUEL_PER_FRAME).map_err(|err| err.to_string())?;
+        let frame = match self.frame {
+            Some((frame_len, addr)) if frame_len == len => addr,
+            _ => {
+                let addr = self.frame_buffer.call(&mut self.store, len as i32).map_err(|err| err.to_string())? as u32 as usize;
+                self.frame = Some((len, addr));
+                addr
+            }
+        };
+        if let Some(params_buffer) = &self.params_buffer {
+            let values: Vec<u8> = self.params.iter().flat_map(|spec| param_value(spec, params).to_le_bytes()).collect();
+            let addr = params_buffer.call(&mut self.store, self.params.len() as i32).map_err(|err| err.to_string())? as u32 as usize;
+            self.memory.write(&mut self.store, addr, &values).map_err(|_| String::from("params buffer is outside the plugin's memory"))?;
+        }
+        self.render.call(&mut self.store, (len as i32, time)).map_err(|err| err.to_string())?;
+        let mut bytes = vec![0; len * 3];
+        self.memory.read(&self.store, frame, &mut bytes).map_err(|_| String::from("frame buffer is outside the plugin's memory"))?;
+        Ok(bytes.chunks_exact(3).map(|c| ColorRgb::new(c[0], c[1], c[2])).collect())
+    }
+}
+
+// a param as the f32 handed to plugins, its default if it isn't set
+fn param_value(spec: &ParamSpec, params: &Params) -> f32 {
+    match params.get(spec.name).unwrap_or(&spec.default) {
+        ParamValue::Number(v) => *v,
+        ParamValue::Bool(v) => *v as u8 as f32,
+        ParamValue::Text(v) => spec.options.iter().flatten().position(|o| o == v).unwrap_or(0) as f32,
+        ParamValue::Color(_) => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fills the frame with `level` * 255 in every channel, the frame buffer at 1024 and the
+    // params at 0
+    const GLOW: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "frame_buffer") (param i32) (result i32) i32.const 1024)
+            (func (export "params_buffer") (param i32) (result i32) i32.const 0)
+            (func (export "render") (param $len i32) (param $time f32)
+                (local $i i32)
+                (local $v i32)
+                (local.set $v (i32.trunc_f32_u (f32.mul (f32.load (i32.const 0)) (f32.const 255))))
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.ge_u (local.get $i) (i32.mul (local.get $len) (i32.const 3))))
+                        (i32.store8 (i32.add (i32.const 1024) (local.get $i)) (local.get $v))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))))
+    "#;
+
+    const SPIN: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "frame_buffer") (param i32) (result i32) i32.const 0)
+            (func (export "render") (param i32) (param f32) (loop $forever (br $forever))))
+    "#;
+
+    fn level_spec() -> PluginSpec {
+        serde_json::from_str(r#"{"name": "glow", "params": [{"name": "level", "type": "float", "min": 0, "max": 1, "default": 0.5}]}"#).unwrap()
+    }
+
+    #[test]
+    fn test_plugins() {
+        let mut plugins = Plugins::new();
+        assert!(plugins.add("glow", &wat::parse_str(GLOW).unwrap(), level_spec()).is_ok());
+
+        // -test- plugins are listed as patterns after the built-in ones
+        let info = plugins.patterns().next().unwrap();
+        assert_eq!((info.id, info.name), (FIRST_PLUGIN, "glow"));
+        assert!(info.validate(&Params::new().with("level", ParamValue::Number(2.0))).is_err());
+
+        // -test- the plugin draws into its frame buffer using the params
+        let mut instance = plugins.instantiate(FIRST_PLUGIN).unwrap();
+        assert_eq!(instance.render(2, 0.0, &Params::new()).unwrap(), [ColorRgb::new(127, 127, 127); 2]);
+        let full = Params::new().with("level", ParamValue::Number(1.0));
+        assert_eq!(instance.render(3, 0.0, &full).unwrap(), [ColorRgb::new(255, 255, 255); 3]);
+
+        // -test- endless loops run out of fuel
+        assert!(plugins.add("spin", &wat::parse_str(SPIN).unwrap(), PluginSpec::default()).is_ok());
+        let mut spin = plugins.instantiate(FIRST_PLUGIN + 1).unwrap();
+        assert!(spin.render(2, 0.0, &Params::new()).is_err());
+
+        // -test- plugins that import anything or miss an export are rejected
+        let imports = wat::parse_str(r#"(module (import "env" "f" (func)))"#).unwrap();
+        assert!(plugins.add("imports", &imports, PluginSpec::default()).is_err());
+        let missing = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert!(plugins.add("missing", &missing, PluginSpec::default()).is_err());
+        assert!(plugins.add("glow2", &wat::parse_str(SPIN).unwrap(), level_spec()).is_err());
+    }
+}
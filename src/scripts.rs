@@ -0,0 +1,350 @@
+// user scripts for the script pattern, written in Rhai. A script defines either
+// `render(i, t, len, params)`, called for every pixel, or `frame(t, len, params)`, called once
+// per frame and returning an array with a color per pixel. Colors are made with `hsv(h, s, v)`
+// from 0.0 to 1.0 or `rgb(r, g, b)` from 0 to 255.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::Serialize;
+
+use crate::colors::{ColorHsv, ColorRgb16};
+use crate::params::{ParamValue, Params};
+
+pub const MAX_SOURCE: usize = 16384;
+pub const MAX_SCRIPTS: usize = 64;
+
+const DIR: &str = "scripts";
+// operations allowed in one call of `render`, and in one call of `frame`
+const PIXEL_OPERATIONS: u64 = 20_000;
+const FRAME_OPERATIONS: u64 = 2_000_000;
+// share of the frame period that all the scripts running in a frame get between them, after
+// which the rest of their pixels are left black, checked every `CLOCK_INTERVAL` operations
+const FRAME_SHARE: u32 = 4;
+const CLOCK_INTERVAL: u64 = 256;
+const OUT_OF_TIME: &str = "script ran out of time for the frame";
+
+#[derive(Debug, Clone)]
+struct Script {
+    source: String,
+    ast: AST,
+    // whether the script defines `frame` rather than `render`
+    per_frame: bool,
+    // error the script stopped with the last time it ran
+    error: Option<String>,
+}
+
+/// A script as listed by `/scripts`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ScriptStatus<'a> {
+    pub name: &'a str,
+    pub error: Option<&'a str>,
+}
+
+/// Every script by name, and the sandboxed engine that runs them. The engine has no access to
+/// files or the network, and is limited in how long it runs and how much memory it takes.
+#[derive(Debug)]
+pub struct Scripts {
+    engine: Engine,
+    scripts: BTreeMap<String, Script>,
+    // when the scripts for the current frame have to be finished by, read by the engine as
+    // they run
+    deadline: Arc<Mutex<Instant>>,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let mut engine = Engine::new();
+        engine
+            .set_max_call_levels(16)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(1024)
+            .set_max_array_size(65536)
+            .set_max_map_size(256)
+            .set_max_operations(PIXEL_OPERATIONS);
+        engine.disable_symbol("eval");
+        // scripts run every frame, so anything they print would flood the server's output
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        let frame_end = deadline.clone();
+        engine.on_progress(move |ops| {
+            if ops % CLOCK_INTERVAL != 0 {
+                return None;
+            }
+            let frame_end = match frame_end.lock() {
+                Ok(guard) => *guard,
+                Err(poisoned) => *poisoned.into_inner(),
+            };
+            (Instant::now() > frame_end).then(|| Dynamic::from(OUT_OF_TIME))
+        });
+        engine.register_fn("hsv", |h: Dynamic, s: Dynamic, v: Dynamic| {
+            color_map(&[("h", h), ("s", s), ("v", v)])
+        });
+        engine.register_fn("rgb", |r: Dynamic, g: Dynamic, b: Dynamic| {
+            color_map(&[("r", r), ("g", g), ("b", b)])
+        });
+        Self { engine, scripts: BTreeMap::new(), deadline }
+    }
+
+    pub fn get_source(&self, name: &str) -> Option<&str> {
+        self.scripts.get(name).map(|s| s.source.as_str())
+    }
+
+    pub fn list(&self) -> Vec<ScriptStatus<'_>> {
+        self.scripts
+            .iter()
+            .map(|(name, script)| ScriptStatus { name, error: script.error.as_deref() })
+            .collect()
+    }
+
+    /// Checks that the `script` param, if set, names an existing script.
+    pub fn check(&self, params: &Params) -> Result<(), String> {
+        match params.get("script") {
+            Some(_) if !self.scripts.contains_key(params.get_str("script", "")) => Err(String::from("unknown script")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compiles `source` and stores it as `name`, replacing any script of that name. Returns
+    /// the compile error, with its line and position, if it doesn't compile.
+    pub fn set(&mut self, name: &str, source: &str) -> Result<(), String> {
+        if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(String::from("script names are 1 to 32 letters, digits, '_' or '-'"));
+        }
+        if source.len() > MAX_SOURCE {
+            return Err(String::from("script is too long"));
+        }
+        if !self.scripts.contains_key(name) && self.scripts.len() >= MAX_SCRIPTS {
+            return Err(String::from("too many scripts"));
+        }
+        let ast = self.engine.compile(source).map_err(|err| err.to_string())?;
+        let per_frame = match (has_fn(&ast, "render", 4), has_fn(&ast, "frame", 3)) {
+            (_, true) => true,
+            (true, false) => false,
+            _ => return Err(String::from("script must define render(i, t, len, params) or frame(t, len, params)")),
+        };
+        let script = Script { source: String::from(source), ast, per_frame, error: None };
+        self.scripts.insert(String::from(name), script);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), &'static str> {
+        match self.scripts.remove(name) {
+            Some(_) => Ok(()),
+            None => Err("unknown script"),
+        }
+    }
+
+    /// Adds every script in the scripts directory, reporting those that don't compile.
+    pub fn load(&mut self) {
+        let entries = match fs::read_dir(DIR) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension().is_some_and(|e| e == "rhai") {
+                let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+                let result = fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|src| self.set(&name, &src));
+                if let Err(err) = result {
+                    println!("error loading script {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    /// Writes the script `name` to the scripts directory, or deletes its file if it has been
+    /// removed.
+    pub fn save(&self, name: &str) {
+        let path = Path::new(DIR).join(format!("{}.rhai", name));
+        match self.scripts.get(name) {
+            Some(script) => {
+                let _ = fs::create_dir_all(DIR);
+                let _ = fs::write(path, &script.source);
+            }
+            None => {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Starts the time the scripts share for a frame lasting `period`, so that slow scripts
+    /// can't hold up the frame.
+    pub fn start_frame(&mut self, period: Duration) {
+        let deadline = Instant::now() + period / FRAME_SHARE;
+        match self.deadline.lock() {
+            Ok(mut guard) => *guard = deadline,
+            Err(poisoned) => *poisoned.into_inner() = deadline,
+        }
+    }
+
+    /// Runs the script `name` for a frame of `len` pixels, `time` seconds into the animation.
+    /// Pixels the script didn't get to, because it failed or ran out of the frame's time, are
+    /// black, and the error is kept for `/scripts` until the script next runs cleanly.
+    pub fn run(&mut self, name: &str, time: f32, len: usize, params: &Params) -> Vec<ColorRgb16> {
+        let mut pixels = vec![ColorRgb16::default(); len];
+        let script = match self.scripts.get_mut(name) {
+            Some(script) => script,
+            None => return pixels,
+        };
+        let args = params_map(params);
+        let options = || CallFnOptions::new().eval_ast(false);
+        let deadline = match self.deadline.lock() {
+            Ok(guard) => *guard,
+            Err(poisoned) => *poisoned.into_inner(),
+        };
+        let result = if script.per_frame {
+            self.engine.set_max_operations(FRAME_OPERATIONS);
+            let out = self.engine.call_fn_with_options::<Dynamic>(options(), &mut Scope::new(), &script.ast, "frame", (time as f64, len as i64, args));
+            self.engine.set_max_operations(PIXEL_OPERATIONS);
+            out.map_err(error_message).and_then(|out| {
+                let colors = out.into_array().map_err(|_| String::from("frame must return an array of colors"))?;
+                for (pixel, color) in pixels.iter_mut().zip(colors) {
+                    *pixel = to_color(&color)?;
+                }
+                Ok(())
+            })
+        } else {
+            (0..len).try_for_each(|i| {
+                if Instant::now() > deadline {
+                    return Err(String::from(OUT_OF_TIME));
+                }
+                let args = (i as i64, time as f64, len as i64, args.clone());
+                let color = self.engine.call_fn_with_options::<Dynamic>(options(), &mut Scope::new(), &script.ast, "render", args).map_err(error_message)?;
+                pixels[i] = to_color(&color)?;
+                Ok(())
+            })
+        };
+        script.error = result.err();
+        pixels
+    }
+}
+
+// what stopped a script, with running out of time put plainly rather than as a termination
+fn error_message(err: Box<EvalAltResult>) -> String {
+    match err.unwrap_inner() {
+        EvalAltResult::ErrorTerminated(..) => String::from(OUT_OF_TIME),
+        _ => err.to_string(),
+    }
+}
+
+fn has_fn(ast: &AST, name: &str, params: usize) -> bool {
+    ast.iter_functions().any(|f| f.name == name && f.params.len() == params)
+}
+
+fn color_map(channels: &[(&str, Dynamic)]) -> Map {
+    channels.iter().map(|(name, value)| ((*name).into(), Dynamic::from_float(number(value).unwrap_or(0.0)))).collect()
+}
+
+// an integer or float as a float
+fn number(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|v| v as f64))
+}
+
+// a script's color at 16 bits, so dim colors keep their level until the frame is encoded
+fn to_color(color: &Dynamic) -> Result<ColorRgb16, String> {
+    let invalid = || String::from("colors must be made with hsv(h, s, v) or rgb(r, g, b)");
+    let map = color.read_lock::<Map>().ok_or_else(invalid)?;
+    let channel = |name: &str| map.get(name).and_then(number);
+    if let (Some(h), Some(s), Some(v)) = (channel("h"), channel("s"), channel("v")) {
+        let hsv = ColorHsv::new((h as f32).rem_euclid(1.0), (s as f32).clamp(0.0, 1.0), (v as f32).clamp(0.0, 1.0));
+        return Ok(hsv.to_rgb16());
+    }
+    match (channel("r"), channel("g"), channel("b")) {
+        (Some(r), Some(g), Some(b)) => {
+            let level = |v: f64| (v.clamp(0.0, 255.0) * 257.0).round() as u16;
+            Ok(ColorRgb16::new(level(r), level(g), level(b)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// the pattern params as a Rhai object map, colors as `#{h, s, v}`
+fn params_map(params: &Params) -> Map {
+    params
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                ParamValue::Bool(v) => Dynamic::from_bool(*v),
+                ParamValue::Number(v) => Dynamic::from_float(*v as f64),
+                ParamValue::Text(v) => Dynamic::from(v.clone()),
+                ParamValue::Color(c) => Dynamic::from_map(color_map(&[
+                    ("h", Dynamic::from_float(c.get_h() as f64)),
+                    ("s", Dynamic::from_float(c.get_s() as f64)),
+                    ("v", Dynamic::from_float(c.get_v() as f64)),
+                ])),
+            };
+            (name.into(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts() {
+        let mut scripts = Scripts::new();
+        let params = Params::new().with("p1", ParamValue::Number(0.5));
+        scripts.start_frame(Duration::from_secs(4));
+
+        // -test- a render script is called for each pixel with the params
+        let ramp = "fn render(i, t, len, params) { rgb(i * 100, params.p1 * 255, t) }";
+        assert!(scripts.set("ramp", ramp).is_ok());
+        let pixels = scripts.run("ramp", 2.0, 3, &params);
+        assert_eq!(pixels, [ColorRgb16::new(0, 32768, 514), ColorRgb16::new(25700, 32768, 514), ColorRgb16::new(51400, 32768, 514)]);
+
+        // -test- a frame script returns every pixel at once
+        let solid = "fn frame(t, len, params) { let out = []; for i in 0..len { out.push(hsv(0, 1, 1)); } out }";
+        assert!(scripts.set("solid", solid).is_ok());
+        assert_eq!(scripts.run("solid", 0.0, 2, &params), [ColorRgb16::new(65535, 0, 0); 2]);
+
+        // -test- compile errors are returned with their position
+        let err = scripts.set("broken", "fn render(i, t, len, params) { hsv(1, 1 }").unwrap_err();
+        assert!(err.contains("line 1"));
+        assert!(scripts.set("empty", "let x = 1;").is_err());
+        assert!(scripts.set("../escape", ramp).is_err());
+
+        // -test- runaway scripts are stopped, leave the frame black and report the error
+        let endless = "fn render(i, t, len, params) { loop { } }";
+        assert!(scripts.set("endless", endless).is_ok());
+        scripts.start_frame(Duration::from_secs(4));
+        assert_eq!(scripts.run("endless", 0.0, 2, &params), [ColorRgb16::default(); 2]);
+        let status = scripts.list().into_iter().find(|s| s.name == "endless").unwrap();
+        assert!(status.error.is_some_and(|e| e.contains("operations")));
+
+        // -test- frame scripts are cut off once the frame's time is up, however few operations
+        // they have used
+        let slow = "fn frame(t, len, params) { let a = []; a.pad(60000, 0); loop { a.reverse(); } }";
+        assert!(scripts.set("slow", slow).is_ok());
+        let period = Duration::from_millis(20);
+        let start = Instant::now();
+        scripts.start_frame(period);
+        assert_eq!(scripts.run("slow", 0.0, 2, &params), [ColorRgb16::default(); 2]);
+        assert!(start.elapsed() < period * 10);
+        let status = scripts.list().into_iter().find(|s| s.name == "slow").unwrap();
+        assert_eq!(status.error, Some(OUT_OF_TIME));
+
+        // -test- the time is shared by every script in the frame, and an error is cleared once
+        // the script runs cleanly again
+        assert_eq!(scripts.run("ramp", 2.0, 1, &params), [ColorRgb16::default()]);
+        assert!(scripts.list().iter().any(|s| s.name == "ramp" && s.error == Some(OUT_OF_TIME)));
+        scripts.start_frame(Duration::from_secs(4));
+        assert_eq!(scripts.run("ramp", 2.0, 1, &params), [ColorRgb16::new(0, 32768, 514)]);
+        assert!(scripts.list().iter().any(|s| s.name == "ramp" && s.error.is_none()));
+
+        // -test- returning something other than a color is an error
+        assert!(scripts.set("number", "fn render(i, t, len, params) { 1 }").is_ok());
+        scripts.run("number", 0.0, 1, &params);
+        assert!(scripts.list().iter().any(|s| s.name == "number" && s.error.is_some()));
+
+        // -test- the script param must name an existing script
+        assert!(scripts.check(&Params::new().with("script", ParamValue::Text(String::from("ramp")))).is_ok());
+        assert!(scripts.check(&Params::new().with("script", ParamValue::Text(String::from("nope")))).is_err());
+    }
+}
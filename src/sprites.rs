@@ -1,48 +1,44 @@
-use fastrand;
+use crate::noise::perlin3;
 
+// how far along its noise lane a sprite moves each frame, lower gives slower changes of pace
+const NOISE_STEP: f32 = 0.02;
+
+/// A blob drifting along the strip with its velocity taken from a lane of Perlin noise, so it
+/// speeds up, slows down and turns around smoothly instead of jumping between random speeds.
 #[derive(Copy, Clone, Debug)]
 pub struct Sprite {
     pos: f32,
     falloff: f32,
-    speed: f32,
+    // where in the noise this sprite samples its velocity, so sprites don't move in step
+    lane: f32,
+    time: f32,
+    // flipped when the sprite bounces off an end
+    heading: f32,
     max_speed: f32,
 }
 
 impl Sprite {
-    pub fn new(pos: f32, falloff: f32, mut speed: f32, max_speed: f32) -> Self {
-        let max_speed_clip = max_speed.max(0.001);
-        if speed == 0.0 { 
-            speed = 0.0001; 
-        } else if speed > 0.0 {
-            speed = speed.max(0.0001).min(max_speed_clip);
-        } else if speed < 0.0 {
-            speed = speed.min(-0.0001).max(max_speed_clip * -1.0);
-        }
-        //if speed > 0.0 && speed < 0.0001 { speed = 0.0001 }
-        //if speed < 0.0 && speed > -0.0001 { speed = -0.0001 }
+    pub fn new(pos: f32, falloff: f32, lane: f32, max_speed: f32) -> Self {
         Self {
-            pos: pos.max(0.0).min(1.0),
+            pos: pos.clamp(0.0, 1.0),
             falloff: falloff.max(1.0),
-            speed: speed,
-            max_speed: max_speed_clip,
+            lane,
+            time: 0.0,
+            heading: 1.0,
+            max_speed: max_speed.max(0.0001),
         }
     }
 
     pub fn run(&mut self) {
-        if self.speed > 0.0 {
-            self.pos = self.pos + self.speed;
-            if self.pos >= 1.0 {
-                self.pos = 1.0;
-                //self.speed = self.speed * -1.0;
-                self.speed = (fastrand::f32() * self.max_speed).max(0.0001) * -1.0;
-            }
-        } else if self.speed < 0.0 {
-            self.pos = self.pos + self.speed;
-            if self.pos <= 0.0 {
-                self.pos = 0.0;
-                //self.speed = self.speed * -1.0;
-                self.speed = (fastrand::f32() * self.max_speed).max(0.0001);
-            }
+        self.time += NOISE_STEP;
+        let velocity = (perlin3(self.time, self.lane, 0.5) * 2.0).clamp(-1.0, 1.0) * self.max_speed;
+        self.pos += velocity * self.heading;
+        if self.pos > 1.0 {
+            self.pos = (2.0 - self.pos).max(0.0);
+            self.heading = -self.heading;
+        } else if self.pos < 0.0 {
+            self.pos = (-self.pos).min(1.0);
+            self.heading = -self.heading;
         }
     }
 
@@ -54,3 +50,166 @@ impl Sprite {
         self.falloff
     }
 }
+
+/// A bright head moving along the strip, followed by a tail `length` long.
+#[derive(Copy, Clone, Debug)]
+pub struct Comet {
+    pos: f32,
+    // 1.0 moving toward the end of the strip, -1.0 toward the start
+    heading: f32,
+    length: f32,
+    wrap: bool,
+}
+
+impl Comet {
+    pub fn new(pos: f32, heading: f32) -> Self {
+        Self { pos: pos.clamp(0.0, 1.0), heading: heading.signum(), length: 0.0, wrap: true }
+    }
+
+    /// Moves the head by `speed`, either wrapping around or bouncing back at the ends, and
+    /// sets the length of the tail.
+    pub fn run(&mut self, speed: f32, length: f32, wrap: bool) {
+        self.length = length;
+        self.wrap = wrap;
+        self.pos += speed * self.heading;
+        if wrap {
+            self.pos = self.pos.rem_euclid(1.0);
+        } else if self.pos > 1.0 {
+            self.pos = (2.0 - self.pos).max(0.0);
+            self.heading = -1.0;
+        } else if self.pos < 0.0 {
+            self.pos = (-self.pos).min(1.0);
+            self.heading = 1.0;
+        }
+    }
+
+    pub fn get_length(&self) -> f32 {
+        self.length
+    }
+
+    /// How far `pos` lies behind the head, 0.0 at the head to 1.0 at the end of the tail, or
+    /// `None` if it is outside the comet.
+    pub fn trail(&self, pos: f32) -> Option<f32> {
+        let mut behind = (self.pos - pos) * self.heading;
+        if self.wrap {
+            behind = behind.rem_euclid(1.0);
+        }
+        if behind >= 0.0 && behind <= self.length {
+            Some(behind / self.length.max(f32::EPSILON))
+        } else {
+            None
+        }
+    }
+}
+
+/// A ball falling under gravity toward the first LED, keeping `restitution` of its speed on
+/// each bounce and dropped from the top again once it has come to rest.
+#[derive(Copy, Clone, Debug)]
+pub struct Ball {
+    // height above the first LED, 0.0 to 1.0
+    pos: f32,
+    // in strip lengths per second, positive upward
+    velocity: f32,
+    restitution: f32,
+}
+
+impl Ball {
+    pub fn new(restitution: f32) -> Self {
+        Self { pos: 1.0, velocity: 0.0, restitution: restitution.clamp(0.0, 0.99) }
+    }
+
+    /// Advances the ball `dt` seconds under `gravity`, in strip lengths per second squared.
+    pub fn run(&mut self, dt: f32, gravity: f32) {
+        self.velocity -= gravity * dt;
+        self.pos += self.velocity * dt;
+        if self.pos <= 0.0 {
+            self.pos = 0.0;
+            self.velocity = -self.velocity * self.restitution;
+            // once the bounces are too small to see, drop it again: at this speed it rises
+            // less than 1% of the strip
+            if self.velocity * self.velocity < 2.0 * gravity * 0.01 {
+                self.pos = 1.0;
+                self.velocity = 0.0;
+            }
+        }
+        self.pos = self.pos.min(1.0);
+    }
+
+    pub fn get_pos(&self) -> f32 {
+        self.pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sprite() {
+        // -test- sprites move smoothly, stay on the strip and repeat for the same lane
+        let mut a = Sprite::new(0.5, 4.0, 12.7, 0.02);
+        let mut b = Sprite::new(0.5, 4.0, 12.7, 0.02);
+        let mut prev = a.get_pos();
+        let mut moved = 0.0;
+        for _ in 0..2000 {
+            a.run();
+            b.run();
+            assert_eq!(a.get_pos(), b.get_pos());
+            assert!((0.0..=1.0).contains(&a.get_pos()));
+            assert!((a.get_pos() - prev).abs() <= 0.02 + f32::EPSILON);
+            moved += (a.get_pos() - prev).abs();
+            prev = a.get_pos();
+        }
+        assert!(moved > 1.0);
+    }
+
+    #[test]
+    fn test_ball() {
+        // -test- each bounce is lower than the last until the ball is dropped again
+        let mut ball = Ball::new(0.8);
+        let mut peaks = Vec::new();
+        let mut rising = false;
+        let mut dropped = false;
+        for _ in 0..2000 {
+            let before = ball.get_pos();
+            ball.run(0.01, 9.8);
+            assert!((0.0..=1.0).contains(&ball.get_pos()));
+            if ball.get_pos() == 1.0 && before < 0.5 {
+                dropped = true;
+                break;
+            }
+            if rising && ball.get_pos() < before {
+                peaks.push(before);
+            }
+            rising = ball.get_pos() > before;
+        }
+        assert!(dropped);
+        assert!(peaks.len() > 3);
+        assert!(peaks.windows(2).all(|w| w[1] < w[0]));
+        assert!(peaks[0] < 0.7);
+    }
+
+    #[test]
+    fn test_comet() {
+        // -test- the tail trails behind the direction of travel
+        let mut comet = Comet::new(0.4, 1.0);
+        comet.run(0.1, 0.2, false);
+        assert_eq!(comet.trail(0.5), Some(0.0));
+        assert!((comet.trail(0.4).unwrap() - 0.5).abs() < 0.001);
+        assert_eq!(comet.trail(0.6), None);
+        assert_eq!(comet.trail(0.2), None);
+
+        // -test- bouncing turns the comet around at the end
+        comet.run(0.6, 0.2, false);
+        assert!((comet.pos - 0.9).abs() < 0.001);
+        assert!(comet.trail(1.0).is_some());
+        assert_eq!(comet.trail(0.8), None);
+
+        // -test- wrapping carries the comet and its tail over the end
+        let mut comet = Comet::new(0.95, 1.0);
+        comet.run(0.1, 0.2, true);
+        assert!((comet.pos - 0.05).abs() < 0.001);
+        assert!(comet.trail(0.95).is_some());
+        assert_eq!(comet.trail(0.3), None);
+    }
+}
@@ -0,0 +1,80 @@
+// independent twinkles: dark pixels ignite at random, fade up to full and back down at their
+// own speed, then wait to be lit again
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Twinkle {
+    // position in the fade, 0.0 to 2.0, brightest at 1.0
+    age: f32,
+    // fade progress per frame, 0.0 while the pixel is dark
+    rate: f32,
+    // which of the three colors this twinkle shows
+    color: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Twinkles {
+    pixels: Vec<Twinkle>,
+}
+
+impl Twinkles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Brightness of pixel `i` from 0.0 to 1.0 and the index of its color.
+    pub fn get(&self, i: usize) -> (f32, usize) {
+        match self.pixels.get(i) {
+            Some(t) if t.rate > 0.0 => ((t.age * std::f32::consts::FRAC_PI_2).sin(), t.color as usize),
+            _ => (0.0, 0),
+        }
+    }
+
+    /// Advances every twinkle one frame. `density` is the chance a dark pixel ignites each
+    /// frame, `fade` the average change in brightness per frame.
+    pub fn step(&mut self, len: usize, density: f32, fade: f32, rng: &mut fastrand::Rng) {
+        if self.pixels.len() != len {
+            self.pixels = vec![Twinkle::default(); len];
+        }
+        for t in self.pixels.iter_mut() {
+            if t.rate > 0.0 {
+                t.age += t.rate;
+                if t.age >= 2.0 {
+                    *t = Twinkle::default();
+                }
+            } else if rng.f32() < density {
+                // each twinkle fades between half and one and a half times the set speed
+                t.rate = fade * (0.5 + rng.f32());
+                t.color = rng.u8(0..3);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_twinkles() {
+        // -test- the same seed gives the same twinkles
+        let mut a = Twinkles::new();
+        let mut b = Twinkles::new();
+        let mut rng_a = fastrand::Rng::with_seed(3);
+        let mut rng_b = fastrand::Rng::with_seed(3);
+        for _ in 0..50 {
+            a.step(40, 0.05, 0.05, &mut rng_a);
+            b.step(40, 0.05, 0.05, &mut rng_b);
+        }
+        assert_eq!(a.pixels.len(), 40);
+        assert!((0..40).all(|i| a.get(i) == b.get(i)));
+        assert!((0..40).any(|i| a.get(i).0 > 0.0));
+        assert!((0..40).all(|i| a.get(i).0 <= 1.0 && a.get(i).1 < 3));
+
+        // -test- with no new ignitions every twinkle fades out
+        for _ in 0..100 {
+            a.step(40, 0.0, 0.05, &mut rng_a);
+        }
+        assert!((0..40).all(|i| a.get(i).0 == 0.0));
+        assert_eq!(a.get(100), (0.0, 0));
+    }
+}
@@ -1,13 +1,16 @@
 // build command:
 // wasm-pack build --release --target web
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+
 use serde::{Serialize, Deserialize};
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::{JsFuture, spawn_local};
 
-use web_sys::{console, HtmlInputElement, HtmlOptionElement, HtmlSelectElement, Request, RequestInit, RequestMode, Response, SvgElement};
+use web_sys::{console, Element, HtmlElement, HtmlInputElement, HtmlOptionElement, HtmlSelectElement, Request, RequestInit, RequestMode, Response, SvgElement};
 
 struct Pixel {
     r: u8,
@@ -15,7 +18,7 @@ struct Pixel {
     b: u8,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct ColorHsv {
     h: f64,
     s: f64,
@@ -38,6 +41,62 @@ struct LedState {
     color2: ColorHsv,
     color3: ColorHsv,
     pattern: u8,
+    #[serde(default)]
+    params: Option<HashMap<String, ParamValue>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum ParamValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    Color(ColorHsv),
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ParamType {
+    Color,
+    Float,
+    Int,
+    Bool,
+    Enum,
+    Palette,
+    Script,
+    Media,
+    Text,
+}
+
+#[derive(Deserialize, Clone)]
+struct ParamSpec {
+    name: String,
+    #[serde(rename = "type")]
+    kind: ParamType,
+    min: Option<f64>,
+    max: Option<f64>,
+    options: Option<Vec<String>>,
+    default: ParamValue,
+}
+
+#[derive(Deserialize, Clone)]
+struct PatternInfo {
+    id: u8,
+    name: String,
+    params: Vec<ParamSpec>,
+}
+
+thread_local! {
+    // pattern schema from /patterns
+    static PATTERNS: RefCell<Vec<PatternInfo>> = RefCell::new(Vec::new());
+    // params of the selected device, as last read from /get
+    static PARAMS: RefCell<HashMap<String, ParamValue>> = RefCell::new(HashMap::new());
+    // palette names from /palettes
+    static PALETTES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    // script names from /scripts
+    static SCRIPTS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    // image and animation names from /media
+    static MEDIA: RefCell<Vec<String>> = RefCell::new(Vec::new());
 }
 
 impl LedState {
@@ -47,6 +106,7 @@ impl LedState {
             color2: ColorHsv::new(c2h, c2s, c2v),
             color3: ColorHsv::new(c3h, c3s, c3v),
             pattern: p,
+            params: None,
         }
     }
 } 
@@ -169,6 +229,163 @@ fn set_options(id: &str, options: Vec<String>) {
     }
 }
 
+fn create_element(tag: &str, id: &str, class: &str) -> Element {
+    let element = document().create_element(tag).unwrap();
+    if !id.is_empty() {
+        element.set_id(id);
+    }
+    element.set_class_name(class);
+    element
+}
+
+fn set_visible(id: &str, visible: bool) {
+    if let Some(element) = document().get_element_by_id(id) {
+        if let Ok(element) = element.dyn_into::<HtmlElement>() {
+            let display = if visible { "" } else { "none" };
+            element.style().set_property("display", display).unwrap();
+        }
+    }
+}
+
+fn get_pattern(id: u8) -> Option<PatternInfo> {
+    PATTERNS.with(|p| p.borrow().iter().find(|info| info.id == id).cloned())
+}
+
+// shows the color boxes the pattern uses and builds a control for each of its other params
+fn show_params(pattern: u8) {
+    let info = match get_pattern(pattern) {
+        Some(v) => v,
+        None => return,
+    };
+    for color in ["color1", "color2", "color3"] {
+        let used = info.params.iter().any(|p| p.kind == ParamType::Color && p.name == color);
+        set_visible(&format!("{}_box", color), used);
+    }
+
+    let container = match document().get_element_by_id("params") {
+        Some(v) => v,
+        None => return,
+    };
+    container.set_inner_html("");
+    let head = create_element("p", "", "head");
+    head.set_text_content(Some("params"));
+    container.append_child(&head).unwrap();
+
+    let values = PARAMS.with(|p| p.borrow().clone());
+    let mut count = 0;
+    for spec in info.params.iter().filter(|p| p.kind != ParamType::Color) {
+        let value = values.get(&spec.name).unwrap_or(&spec.default);
+        let row = create_element("div", "", "slider_row");
+        let input_id = format!("param_{}", spec.name);
+        match spec.kind {
+            ParamType::Float | ParamType::Int => {
+                let number = match value {
+                    ParamValue::Number(v) => *v,
+                    _ => 0.0,
+                };
+                let min = spec.min.unwrap_or(0.0);
+                let max = spec.max.unwrap_or(1.0);
+                let step = if spec.kind == ParamType::Int { 1.0 } else { (max - min) / 100.0 };
+                let slider = create_element("input", &input_id, "slider").dyn_into::<HtmlInputElement>().unwrap();
+                let text = create_element("input", &format!("{}_text", input_id), "inputs").dyn_into::<HtmlInputElement>().unwrap();
+                for input in [&slider, &text] {
+                    input.set_min(&format!("{}", min));
+                    input.set_max(&format!("{}", max));
+                    input.set_step(&format!("{}", step));
+                    input.set_value(&format!("{}", number));
+                }
+                slider.set_type("range");
+                text.set_type("number");
+                let text_id = text.id();
+                let callback = Closure::wrap(Box::new(move |event: web_sys::Event| {
+                    if let Ok(input) = event.target().unwrap().dyn_into::<HtmlInputElement>() {
+                        set_input(&text_id, &input.value());
+                    }
+                }) as Box<dyn FnMut(_)>);
+                slider.add_event_listener_with_callback("change", callback.as_ref().unchecked_ref()).unwrap();
+                callback.forget();
+                let slider_id = slider.id();
+                let text_callback = Closure::wrap(Box::new(move |event: web_sys::Event| {
+                    if let Ok(input) = event.target().unwrap().dyn_into::<HtmlInputElement>() {
+                        set_input(&slider_id, &input.value());
+                    }
+                }) as Box<dyn FnMut(_)>);
+                text.add_event_listener_with_callback("change", text_callback.as_ref().unchecked_ref()).unwrap();
+                text_callback.forget();
+                row.append_child(&slider).unwrap();
+                row.append_child(&text).unwrap();
+            }
+            ParamType::Bool => {
+                let checkbox = create_element("input", &input_id, "inputs").dyn_into::<HtmlInputElement>().unwrap();
+                checkbox.set_type("checkbox");
+                checkbox.set_checked(matches!(value, ParamValue::Bool(true)));
+                row.append_child(&checkbox).unwrap();
+            }
+            ParamType::Enum | ParamType::Palette | ParamType::Script | ParamType::Media => {
+                let options = match spec.kind {
+                    ParamType::Palette => PALETTES.with(|p| p.borrow().clone()),
+                    ParamType::Script => SCRIPTS.with(|s| s.borrow().clone()),
+                    ParamType::Media => MEDIA.with(|m| m.borrow().clone()),
+                    _ => spec.options.clone().unwrap_or_default(),
+                };
+                let select = create_element("select", &input_id, "pattern-el").dyn_into::<HtmlSelectElement>().unwrap();
+                for option in options.iter() {
+                    let opt = HtmlOptionElement::new_with_text_and_value(option, option).unwrap();
+                    select.add_with_html_option_element(&opt).unwrap();
+                }
+                if let ParamValue::Text(v) = value {
+                    select.set_value(v);
+                }
+                row.append_child(&select).unwrap();
+            }
+            ParamType::Text => {
+                let input = create_element("input", &input_id, "inputs").dyn_into::<HtmlInputElement>().unwrap();
+                input.set_type("text");
+                if let Some(max) = spec.max {
+                    input.set_max_length(max as i32);
+                }
+                if let ParamValue::Text(v) = value {
+                    input.set_value(v);
+                }
+                row.append_child(&input).unwrap();
+            }
+            ParamType::Color => (),
+        }
+        let label = create_element("label", "", "labels");
+        label.set_text_content(Some(&spec.name));
+        row.append_child(&label).unwrap();
+        container.append_child(&row).unwrap();
+        count += 1;
+    }
+    set_visible("params_box", count > 0);
+}
+
+// JSON object with the current value of every non-color param of the pattern, leaving out
+// selects with nothing to choose from
+fn params_json(pattern: u8) -> String {
+    let params = js_sys::Object::new();
+    if let Some(info) = get_pattern(pattern) {
+        for spec in info.params.iter() {
+            let id = format!("param_{}", spec.name);
+            let value = match spec.kind {
+                ParamType::Float => JsValue::from_f64(get_value(&id)),
+                ParamType::Int => JsValue::from_f64(get_value(&id).round()),
+                ParamType::Bool => JsValue::from_bool(get_input(&id).checked()),
+                ParamType::Enum | ParamType::Palette | ParamType::Script | ParamType::Media => {
+                    match document().get_element_by_id(&id).and_then(|v| v.dyn_into::<HtmlSelectElement>().ok()) {
+                        Some(select) if !select.value().is_empty() => JsValue::from_str(&select.value()),
+                        _ => continue,
+                    }
+                }
+                ParamType::Text => JsValue::from_str(&get_input(&id).value()),
+                ParamType::Color => continue,
+            };
+            let _ = js_sys::Reflect::set(&params, &JsValue::from_str(spec.name.as_str()), &value);
+        }
+    }
+    js_sys::JSON::stringify(&params).ok().and_then(|v| v.as_string()).unwrap_or_else(|| String::from("{}"))
+}
+
 fn hsv_2_rgb(col: &ColorHsv) -> Pixel {
     let mut out = Pixel { r: 0, g: 0, b: 0 };
     let h_wrap = col.h.rem_euclid(1.0);
@@ -240,6 +457,87 @@ async fn get_set_options(path: &str) {
     set_options(path, options);
 }
 
+async fn get_patterns() {
+    let win_proto = window().location().protocol().expect("unable to get window().location().protocol()");
+    let win_host = window().location().host().expect("unable to get window().location().host()");
+    let url = format!("{}//{}/patterns", win_proto, win_host);
+    let mut request_opts = RequestInit::new();
+    request_opts.method("GET");
+    request_opts.mode(RequestMode::Cors);
+    let request = Request::new_with_str_and_init(&url, &request_opts).expect("get_patterns() request failed");
+    let response = JsFuture::from(window().fetch_with_request(&request)).await;
+    if let Ok(v) = response {
+        let resp: Response = v.dyn_into().unwrap();
+        let resp_json = JsFuture::from(resp.json().unwrap()).await.unwrap();
+        let resp_parsed: Result<Vec<PatternInfo>, _> = serde_wasm_bindgen::from_value(resp_json);
+        match resp_parsed {
+            Ok(v) => {
+                // options carry the pattern number, plugins are numbered apart from the rest
+                if let Some(select) = document().get_element_by_id("patterns").and_then(|e| e.dyn_into::<HtmlSelectElement>().ok()) {
+                    for p in v.iter() {
+                        let opt = HtmlOptionElement::new_with_text_and_value(&format!("{} - {}", p.id, p.name), &format!("{}", p.id)).unwrap();
+                        select.add_with_html_option_element(&opt).unwrap();
+                    }
+                }
+                PATTERNS.with(|p| *p.borrow_mut() = v);
+            }
+            Err(e) => { console::log_1(&e.into()) }
+        }
+    }
+}
+
+async fn get_palettes() {
+    let win_proto = window().location().protocol().expect("unable to get window().location().protocol()");
+    let win_host = window().location().host().expect("unable to get window().location().host()");
+    let url = format!("{}//{}/palettes", win_proto, win_host);
+    let mut request_opts = RequestInit::new();
+    request_opts.method("GET");
+    request_opts.mode(RequestMode::Cors);
+    let request = Request::new_with_str_and_init(&url, &request_opts).expect("get_palettes() request failed");
+    let response = JsFuture::from(window().fetch_with_request(&request)).await;
+    if let Ok(v) = response {
+        let resp: Response = v.dyn_into().unwrap();
+        let resp_json = JsFuture::from(resp.json().unwrap()).await.unwrap();
+        let resp_parsed: Result<HashMap<String, Vec<ColorHsv>>, _> = serde_wasm_bindgen::from_value(resp_json);
+        match resp_parsed {
+            Ok(v) => {
+                let mut names: Vec<String> = v.into_keys().collect();
+                names.sort();
+                PALETTES.with(|p| *p.borrow_mut() = names);
+            }
+            Err(e) => { console::log_1(&e.into()) }
+        }
+    }
+}
+
+// an entry of /scripts or /media
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+// names listed by `path`, such as "scripts"
+async fn get_names(path: &str) -> Vec<String> {
+    let win_proto = window().location().protocol().expect("unable to get window().location().protocol()");
+    let win_host = window().location().host().expect("unable to get window().location().host()");
+    let url = format!("{}//{}/{}", win_proto, win_host, path);
+    let mut request_opts = RequestInit::new();
+    request_opts.method("GET");
+    request_opts.mode(RequestMode::Cors);
+    let request = Request::new_with_str_and_init(&url, &request_opts).expect("get_names() request failed");
+    let response = JsFuture::from(window().fetch_with_request(&request)).await;
+    if let Ok(v) = response {
+        let resp: Response = v.dyn_into().unwrap();
+        let resp_json = JsFuture::from(resp.json().unwrap()).await.unwrap();
+        let resp_parsed: Result<Vec<Named>, _> = serde_wasm_bindgen::from_value(resp_json);
+        match resp_parsed {
+            Ok(v) => return v.into_iter().map(|n| n.name).collect(),
+            Err(e) => { console::log_1(&e.into()) }
+        }
+    }
+    Vec::new()
+}
+
 async fn get_leds() {
     let win_proto = window().location().protocol().expect("unable to get window().location().protocol()");
     let win_host = window().location().host().expect("unable to get window().location().host()");
@@ -292,6 +590,8 @@ async fn get_leds() {
                     set_swatch("color3_h", "color3_s", "color3_v", "color3_swatch");
 
                     set_pattern("patterns", v.pattern);
+                    PARAMS.with(|p| *p.borrow_mut() = v.params.unwrap_or_default());
+                    show_params(v.pattern);
 
                 },
                 Err(e) => { console::log_1(&format!("failed to parse JSON: {:?}", e).into()) },
@@ -332,11 +632,12 @@ async fn set_leds() {
         //let body = serde_wasm_bindgen::to_value(&state).unwrap();
         //req_opts.body(Some(&body));
 
-        let body_string = format!(r#"{{"color1": {{"h": {}, "s": {}, "v": {}}}, "color2": {{"h": {}, "s": {}, "v": {}}}, "color3": {{"h": {}, "s": {}, "v": {}}}, "pattern": {}}}"#, 
+        let body_string = format!(r#"{{"color1": {{"h": {}, "s": {}, "v": {}}}, "color2": {{"h": {}, "s": {}, "v": {}}}, "color3": {{"h": {}, "s": {}, "v": {}}}, "pattern": {}, "params": {}}}"#, 
             &state.color1.h, &state.color1.s, &state.color1.v,
             &state.color2.h, &state.color2.s, &state.color2.v,
             &state.color3.h, &state.color3.s, &state.color3.v,
             &state.pattern,
+            params_json(state.pattern),
         );
         req_opts.body(Some(&JsValue::from_str(&body_string)));
 
@@ -417,6 +718,15 @@ pub async fn run() -> Result<(), JsValue> {
     device_el.add_event_listener_with_callback("change", device_el_callback.as_ref().unchecked_ref())?;
     device_el_callback.forget();
     
+    let pattern_el = document()
+        .get_element_by_id("patterns")
+        .expect("unable to get patterns element");
+    let pattern_el_callback = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        show_params(get_select_value("patterns"));
+    }) as Box<dyn FnMut(_)>);
+    pattern_el.add_event_listener_with_callback("change", pattern_el_callback.as_ref().unchecked_ref())?;
+    pattern_el_callback.forget();
+
     let send_btn = document()
         .get_element_by_id("send")
         .expect("unable to get send element");
@@ -430,7 +740,12 @@ pub async fn run() -> Result<(), JsValue> {
     send_btn.add_event_listener_with_callback("mousedown", send_btn_callback.as_ref().unchecked_ref())?;
     send_btn_callback.forget();
     
-    get_set_options("patterns").await;
+    get_patterns().await;
+    get_palettes().await;
+    let scripts = get_names("scripts").await;
+    SCRIPTS.with(|s| *s.borrow_mut() = scripts);
+    let media = get_names("media").await;
+    MEDIA.with(|m| *m.borrow_mut() = media);
     get_set_options("devices").await;
     get_leds().await;
     